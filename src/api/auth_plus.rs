@@ -1,13 +1,20 @@
-use reqwest::Client;
+use openssl::{pkcs12::Pkcs12, pkey::PKey, x509::X509};
 use serde_json;
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use url::Url;
 use url_serde;
 use zip::ZipArchive;
 
-use config::Config;
+use config::{read_zip_file, Config};
 use error::{Error, Result};
 use http::Http;
+use transport::Request;
 
 
 /// Available Auth+ API methods.
@@ -22,11 +29,13 @@ impl AuthPlusApi for AuthPlus {
     fn refresh_token(config: &mut Config) -> Result<Option<AccessToken>> {
         if let Some(oauth2) = config.credentials()?.oauth2()? {
             debug!("fetching access token from auth-plus server {}", oauth2.server);
-            let req = Client::new()
-                .post(&format!("{}/token", oauth2.server))
-                .basic_auth(oauth2.client_id, Some(oauth2.client_secret))
-                .form(&[("grant_type", "client_credentials")]);
-            Ok(Some(Http::send(req, None)?.json()?))
+            let req = Request::post(&format!("{}/token", oauth2.server))?
+                .basic_auth(&oauth2.client_id, &oauth2.client_secret)
+                .form(&[("grant_type", "client_credentials")])?;
+            let transport = Http::transport(config)?;
+            let mut token: AccessToken = Http::execute(&*transport, &req, None)?.json()?;
+            token.issued_at = Some(now_secs());
+            Ok(Some(token))
         } else {
             debug!("skipping oauth2 authentication...");
             Ok(None)
@@ -42,17 +51,36 @@ pub struct AccessToken {
     pub token_type:   String,
     pub expires_in:   i32,
     pub scope:        String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at:    Option<u64>,
 }
 
 impl AccessToken {
-    pub fn namespace(&self) -> Result<&str> {
-        let scopes = self
-            .scope
+    /// Seconds before the real expiry time that a token is considered expired.
+    const EXPIRY_MARGIN: u64 = 60;
+
+    /// Return every namespace from the token scopes.
+    pub fn namespaces(&self) -> Vec<&str> {
+        self.scope
             .split_whitespace()
             .filter(|s| s.starts_with("namespace."))
-            .map(|s| s.trim_left_matches("namespace."))
-            .collect::<Vec<_>>();
+            .map(|s| s.trim_start_matches("namespace."))
+            .collect()
+    }
+
+    /// Whether the token has expired (or will shortly) and should be refreshed.
+    ///
+    /// Tokens without an `issued_at` time are always considered expired.
+    pub fn is_expired(&self) -> bool {
+        match self.issued_at {
+            Some(issued_at) => now_secs() + Self::EXPIRY_MARGIN >= issued_at + self.expires_in.max(0) as u64,
+            None => true,
+        }
+    }
 
+    /// Return the only namespace from the token scopes.
+    pub fn namespace(&self) -> Result<&str> {
+        let scopes = self.namespaces();
         match scopes.len() {
             1 => Ok(scopes.first().unwrap()),
             0 => Err(Error::Token("namespace not found".into())),
@@ -62,12 +90,20 @@ impl AccessToken {
 }
 
 
+/// Return the current time in seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+
 /// Parsed credentials from `treehub.json` in `credentials.zip`.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Credentials {
     no_auth: Option<bool>,
     oauth2:  Option<OAuth2>,
     ostree:  Ostree,
+    #[serde(skip)]
+    tls: Option<ClientTls>,
 }
 
 impl Credentials {
@@ -75,15 +111,33 @@ impl Credentials {
         debug!("reading treehub.json from zip file: {:?}", credentials_zip.as_ref());
         let file = File::open(credentials_zip)?;
         let mut archive = ZipArchive::new(BufReader::new(file))?;
-        let treehub = archive.by_name("treehub.json")?;
-        Ok(serde_json::from_reader(treehub)?)
+        let mut credentials: Credentials = serde_json::from_reader(archive.by_name("treehub.json")?)?;
+        credentials.tls = ClientTls::from_archive(&mut archive)?;
+        Ok(credentials)
     }
 
+    /// The TLS client identity to present when there is no OAuth2 section.
+    pub fn tls(&self) -> Option<&ClientTls> {
+        match self.oauth2 {
+            Some(_) => None,
+            None => self.tls.as_ref(),
+        }
+    }
+
+    /// The Auth+ server URL for OAuth2 authentication.
+    pub fn auth_server(&self) -> Option<&str> { self.oauth2.as_ref().map(|oauth2| oauth2.server.as_str()) }
+
+    /// The OSTree server URL.
+    pub fn ostree_server(&self) -> &Url { &self.ostree.server }
+
     fn oauth2(&self) -> Result<Option<OAuth2>> {
         if let Some(true) = self.no_auth {
             Ok(None)
         } else if let Some(ref oauth2) = self.oauth2 {
             Ok(Some(oauth2.clone()))
+        } else if self.tls.is_some() {
+            debug!("using tls client authentication...");
+            Ok(None)
         } else {
             Err(Error::Auth("no parseable auth method from credentials.zip".into()))
         }
@@ -102,3 +156,52 @@ struct Ostree {
     #[serde(with = "url_serde")]
     server: Url,
 }
+
+
+/// A TLS client identity (and optional server CA) from `credentials.zip`.
+#[derive(Clone)]
+pub struct ClientTls {
+    /// The DER-encoded PKCS#12 client identity, with an empty password.
+    pub pkcs12: Vec<u8>,
+    /// The PEM-encoded CA certificate to trust for the servers.
+    pub server_ca: Option<Vec<u8>>,
+}
+
+impl ClientTls {
+    const CLIENT_P12: &'static str = "client_auth.p12";
+    const CLIENT_CERT: &'static str = "client.pem";
+    const CLIENT_KEY: &'static str = "pkey.pem";
+    const SERVER_CA: &'static str = "server_ca.pem";
+
+    /// Read either a PKCS#12 bundle or a PEM certificate and key from the archive.
+    pub fn from_archive<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Option<Self>> {
+        let pkcs12 = if let Some(pkcs12) = read_zip_file(archive, Self::CLIENT_P12)? {
+            pkcs12
+        } else {
+            let cert = read_zip_file(archive, Self::CLIENT_CERT)?;
+            let key = read_zip_file(archive, Self::CLIENT_KEY)?;
+            match (cert, key) {
+                (Some(cert), Some(key)) => Self::to_pkcs12(&cert, &key)?,
+                (None, None) => return Ok(None),
+                _ => Err(Error::Auth(format!("both {} and {} are required", Self::CLIENT_CERT, Self::CLIENT_KEY)))?,
+            }
+        };
+        Ok(Some(ClientTls {
+            pkcs12,
+            server_ca: read_zip_file(archive, Self::SERVER_CA)?,
+        }))
+    }
+
+    /// Bundle a PEM certificate and private key as PKCS#12.
+    fn to_pkcs12(cert: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+        let cert = X509::from_pem(cert)?;
+        let key = PKey::private_key_from_pem(key)?;
+        Ok(Pkcs12::builder().name("ota-cli").pkey(&key).cert(&cert).build2("")?.to_der()?)
+    }
+}
+
+impl Debug for ClientTls {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ClientTls {{ pkcs12: <{} bytes>, server_ca: {:?} }}", self.pkcs12.len(), self.server_ca.as_ref().map(Vec::len))
+    }
+}
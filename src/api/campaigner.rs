@@ -1,90 +1,209 @@
 use clap::ArgMatches;
-use reqwest::{Client, Response};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+use api::{
+    pagination::{list_pages, with_page, Page, Pages, Paginated},
+    registry::Registry,
+    resolve::{resolve, Named},
+};
 use config::Config;
 use error::Result;
 use http::{Http, HttpMethods};
+use jobs::run_all;
+use output::{Output, Render};
+use transport::Request;
 
 
 /// Available Campaigner API methods.
 pub trait CampaignerApi {
-    fn create_campaign(&mut Config, update: Uuid, name: &str, groups: &[Uuid]) -> Result<Response>;
-    fn launch_campaign(&mut Config, campaign: Uuid) -> Result<Response>;
-    fn cancel_campaign(&mut Config, campaign: Uuid) -> Result<Response>;
+    fn create_campaign(&mut Config, update: Uuid, name: &str, groups: &[Uuid]) -> Result<Uuid>;
+    fn launch_campaign(&mut Config, campaign: Uuid) -> Result<()>;
+    fn cancel_campaign(&mut Config, campaign: Uuid) -> Result<()>;
 
-    fn list_campaign_info(&mut Config, campaign: Uuid) -> Result<Response>;
-    fn list_campaign_stats(&mut Config, campaign: Uuid) -> Result<Response>;
-    fn list_all_campaigns(&mut Config) -> Result<Response>;
+    fn list_campaign_info(&mut Config, campaign: Uuid) -> Result<Campaign>;
+    fn list_campaign_stats(&mut Config, campaign: Uuid) -> Result<CampaignStats>;
+    fn list_all_campaigns(&mut Config, page: Option<Page>) -> Result<Paginated<Uuid>>;
+    fn find_campaigns(&mut Config, name: &str, page: Option<Page>) -> Result<Paginated<Uuid>>;
 }
 
+/// The number of campaigns fetched at once when looking up a name.
+const LOOKUP_JOBS: usize = 8;
+
 /// Make API calls to manage campaigns.
 pub struct Campaigner;
 
 impl<'a> Campaigner {
     /// Parse CLI arguments to create a new campaign.
-    pub fn create_from_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
+    pub fn create_from_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Uuid> {
         let update = args.value_of("update").expect("--update").parse()?;
         let name = args.value_of("name").expect("--name");
-        let groups = args
-            .values_of("groups")
-            .expect("--groups")
-            .map(Uuid::parse_str)
-            .collect::<::std::result::Result<Vec<_>, _>>()?;
+        let groups = Registry::group_ids(config, &args.values_of("groups").expect("--groups").collect::<Vec<_>>())?;
         Self::create_campaign(config, update, name, &groups)
     }
 
     /// Parse CLI arguments to list campaign information.
-    pub fn list_from_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
-        let campaign = || args.value_of("campaign").expect("--campaign flag").parse();
+    pub fn list_from_args(config: &mut Config, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        let campaign = |config: &mut Config| Self::campaign_id(config, args.value_of("campaign").expect("--campaign flag"));
         if args.is_present("all") {
-            Self::list_all_campaigns(config)
+            list_pages(config, args, out, Self::list_all_campaigns)
         } else if args.is_present("stats") {
-            Self::list_campaign_stats(config, campaign()?)
+            let campaign = campaign(config)?;
+            out.print(&Self::list_campaign_stats(config, campaign)?)
         } else {
-            Self::list_campaign_info(config, campaign()?)
+            let campaign = campaign(config)?;
+            out.print(&Self::list_campaign_info(config, campaign)?)
         }
     }
+
+    /// Find the campaign with this UUID or name.
+    ///
+    /// The campaign list only has IDs, so each campaign whose name contains
+    /// the arg is fetched to compare its full name, `LOOKUP_JOBS` at a time.
+    pub fn campaign_id(config: &mut Config, campaign: &str) -> Result<Uuid> {
+        resolve(config, "campaign", &[campaign], |config| {
+            let mut ids = Vec::new();
+            for page in Pages::new(config, Page::default(), |config, page| Self::find_campaigns(config, campaign, page)) {
+                ids.extend(page?);
+            }
+            let mut named = Vec::new();
+            for (id, info) in ids.clone().into_iter().zip(run_all(config, LOOKUP_JOBS, ids, Self::list_campaign_info)?) {
+                named.push(Named::new(id, vec![info?.name]));
+            }
+            Ok(named)
+        })
+        .map(|mut ids| ids.remove(0))
+    }
 }
 
 impl CampaignerApi for Campaigner {
-    fn create_campaign(config: &mut Config, update: Uuid, name: &str, groups: &[Uuid]) -> Result<Response> {
+    fn create_campaign(config: &mut Config, update: Uuid, name: &str, groups: &[Uuid]) -> Result<Uuid> {
         debug!("creating campaign {} with update {} for groups: {:?}", name, update, groups);
-        let req = Client::new()
-            .post(&format!("{}api/v2/campaigns", config.campaigner))
-            .json(&json!({"update": format!("{}", update), "name": name, "groups": groups}));
-        Http::send(req, config.token()?)
+        let req = Request::post(&format!("{}api/v2/campaigns", config.campaigner))?
+            .json(&json!({"update": format!("{}", update), "name": name, "groups": groups}))?;
+        Http::send(req, config)?.json()
     }
 
-    fn launch_campaign(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    fn launch_campaign(config: &mut Config, campaign: Uuid) -> Result<()> {
         debug!("launching campaign {}", campaign);
-        let req = Client::new().post(&format!("{}api/v2/campaigns/{}/launch", config.campaigner, campaign));
-        Http::send(req, config.token()?)
+        Http::post(&format!("{}api/v2/campaigns/{}/launch", config.campaigner, campaign), config).map(|_| ())
     }
 
-    fn cancel_campaign(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    fn cancel_campaign(config: &mut Config, campaign: Uuid) -> Result<()> {
         debug!("cancelling campaign {}", campaign);
-        Http::post(
-            &format!("{}api/v2/campaigns/{}/cancel", config.campaigner, campaign),
-            config.token()?,
-        )
+        let req = Request::post(&format!("{}api/v2/campaigns/{}/cancel", config.campaigner, campaign))?;
+        Http::send_repeatable(req, config).map(|_| ())
     }
 
-    fn list_campaign_info(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    fn list_campaign_info(config: &mut Config, campaign: Uuid) -> Result<Campaign> {
         debug!("getting info for campaign {}", campaign);
-        Http::get(&format!("{}api/v2/campaigns/{}", config.campaigner, campaign), config.token()?)
+        Http::get(&format!("{}api/v2/campaigns/{}", config.campaigner, campaign), config)?.json()
     }
 
-    fn list_campaign_stats(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    fn list_campaign_stats(config: &mut Config, campaign: Uuid) -> Result<CampaignStats> {
         debug!("getting stats for campaign {}", campaign);
-        Http::get(
-            &format!("{}api/v2/campaigns/{}/stats", config.campaigner, campaign),
-            config.token()?,
-        )
+        Http::get(&format!("{}api/v2/campaigns/{}/stats", config.campaigner, campaign), config)?.json()
     }
 
-    fn list_all_campaigns(config: &mut Config) -> Result<Response> {
+    fn list_all_campaigns(config: &mut Config, page: Option<Page>) -> Result<Paginated<Uuid>> {
         debug!("getting a list of campaigns");
-        Http::get(&format!("{}api/v2/campaigns", config.campaigner), config.token()?)
+        let req = Request::get(&format!("{}api/v2/campaigns", config.campaigner))?;
+        Http::send(with_page(req, page)?, config)?.json()
+    }
+
+    fn find_campaigns(config: &mut Config, name: &str, page: Option<Page>) -> Result<Paginated<Uuid>> {
+        debug!("getting a list of campaigns named like {}", name);
+        let req = Request::get(&format!("{}api/v2/campaigns", config.campaigner))?.query(&[("nameContains", name)])?;
+        Http::send(with_page(req, page)?, config)?.json()
+    }
+}
+
+
+/// A campaign to apply an update to groups of devices.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Campaign {
+    pub id:         Uuid,
+    pub name:       String,
+    pub update:     Uuid,
+    #[serde(default)]
+    pub groups:     Vec<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status:     Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    /// Any other fields returned by the server.
+    #[serde(flatten)]
+    pub extra:      BTreeMap<String, Value>,
+}
+
+impl Render for Campaign {
+    fn columns() -> &'static [&'static str] { &["id", "name", "status", "update", "groups", "createdAt"] }
+}
+
+/// The progress of a campaign across its devices.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignStats {
+    pub campaign:  Uuid,
+    pub status:    String,
+    #[serde(default)]
+    pub processed: u64,
+    #[serde(default)]
+    pub affected:  u64,
+    #[serde(default)]
+    pub finished:  u64,
+    /// The devices that failed to apply the update.
+    #[serde(default)]
+    pub failed:    Vec<Uuid>,
+    #[serde(default)]
+    pub cancelled: u64,
+    /// Processed and affected device counts for each group.
+    #[serde(default)]
+    pub stats:     BTreeMap<Uuid, GroupStats>,
+}
+
+impl Render for CampaignStats {
+    fn columns() -> &'static [&'static str] {
+        &["campaign", "status", "processed", "affected", "finished", "failed", "cancelled"]
+    }
+
+    fn id() -> &'static str { "campaign" }
+}
+
+/// Device counts for one group in a campaign.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupStats {
+    pub processed: u64,
+    pub affected:  u64,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+
+    #[test]
+    fn parse_campaign_stats() {
+        let stats: CampaignStats = serde_json::from_str(
+            r#"{
+                "campaign": "8bdb3f92-5c3c-4fd5-9e3f-5a1d3e1bb1c1",
+                "status": "launched",
+                "finished": 3,
+                "failed": ["a8bbeb7c-85a1-4b9b-9e0c-c1b1d8b3c1b1", "b8bbeb7c-85a1-4b9b-9e0c-c1b1d8b3c1b1"],
+                "cancelled": 0,
+                "processed": 10,
+                "affected": 8,
+                "stats": {"1bdb3f92-5c3c-4fd5-9e3f-5a1d3e1bb1c1": {"processed": 10, "affected": 8}}
+            }"#,
+        )
+        .expect("stats");
+        assert_eq!(stats.failed.len(), 2);
+        assert_eq!(stats.finished, 3);
+        assert_eq!(stats.stats.values().next().map(|group| group.affected), Some(8));
     }
 }
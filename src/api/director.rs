@@ -1,5 +1,4 @@
 use clap::ArgMatches;
-use reqwest::{Client, Response};
 use serde::{self, Deserialize, Deserializer};
 use std::{
     collections::HashMap,
@@ -15,14 +14,15 @@ use uuid::Uuid;
 use config::Config;
 use error::{Error, Result};
 use http::{Http, HttpMethods};
+use transport::Request;
 
 
 /// Available director API methods.
 pub trait DirectorApi {
     /// Create a new multi-target update.
-    fn create_mtu(&mut Config, updates: &TufUpdates) -> Result<Response>;
+    fn create_mtu(&mut Config, updates: &TufUpdates) -> Result<Uuid>;
     /// Launch a multi-target update for a device.
-    fn launch_mtu(&mut Config, update: Uuid, device: Uuid) -> Result<Response>;
+    fn launch_mtu(&mut Config, update: Uuid, device: Uuid) -> Result<()>;
 }
 
 
@@ -30,20 +30,15 @@ pub trait DirectorApi {
 pub struct Director;
 
 impl DirectorApi for Director {
-    fn create_mtu(config: &mut Config, updates: &TufUpdates) -> Result<Response> {
+    fn create_mtu(config: &mut Config, updates: &TufUpdates) -> Result<Uuid> {
         debug!("creating multi-target update: {:?}", updates);
-        let req = Client::new()
-            .post(&format!("{}api/v1/multi_target_updates", config.director))
-            .json(updates);
-        Http::send(req, config.token()?)
+        let req = Request::post(&format!("{}api/v1/multi_target_updates", config.director))?.json(updates)?;
+        Http::send(req, config)?.json()
     }
 
-    fn launch_mtu(config: &mut Config, update: Uuid, device: Uuid) -> Result<Response> {
+    fn launch_mtu(config: &mut Config, update: Uuid, device: Uuid) -> Result<()> {
         debug!("launching multi-target update {} for device {}", update, device);
-        Http::put(
-            &format!("{}api/v1/admin/devices/{}/multi_target_update/{}", config.director, device, update),
-            config.token()?,
-        )
+        Http::put(&format!("{}api/v1/admin/devices/{}/multi_target_update/{}", config.director, device, update), config).map(|_| ())
     }
 }
 
@@ -1,5 +1,7 @@
 pub mod auth_plus;
 pub mod campaigner;
 pub mod director;
+pub mod pagination;
 pub mod registry;
 pub mod reposerver;
+pub mod resolve;
@@ -0,0 +1,195 @@
+use clap::ArgMatches;
+use serde_json::Value;
+use std::marker::PhantomData;
+
+use config::Config;
+use error::{Error, Result};
+use output::{self, Output, Render};
+use transport::Request;
+
+
+/// The page size used with `--all-pages` when no `--limit` is set.
+pub const DEFAULT_LIMIT: u64 = 100;
+
+/// Offset and limit query parameters for paginated list endpoints.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Page {
+    pub offset: u64,
+    pub limit:  u64,
+}
+
+impl<'a> Page {
+    /// Parse the --offset and --limit args, if either is set.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Option<Self>> {
+        let parse = |flag| -> Result<Option<u64>> {
+            match args.value_of(flag) {
+                Some(val) => Ok(Some(val.parse().map_err(|_| Error::Args(format!("--{} must be a number", flag)))?)),
+                None => Ok(None),
+            }
+        };
+        match (parse("offset")?, parse("limit")?) {
+            (None, None) => Ok(None),
+            (offset, limit) => Ok(Some(Page {
+                offset: offset.unwrap_or(0),
+                limit:  limit.unwrap_or(DEFAULT_LIMIT),
+            })),
+        }
+    }
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page {
+            offset: 0,
+            limit:  DEFAULT_LIMIT,
+        }
+    }
+}
+
+/// Add the page parameters to the request query, if set.
+pub fn with_page(req: Request, page: Option<Page>) -> Result<Request> {
+    match page {
+        Some(page) => req.query(&page),
+        None => Ok(req),
+    }
+}
+
+
+/// A single page of results from a list endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Paginated<T> {
+    pub values: Vec<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total:  Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit:  Option<u64>,
+}
+
+impl<T> Paginated<T> {
+    /// The page following this one, if there are more results.
+    ///
+    /// Without a `total` from the server, pages are fetched until one comes
+    /// back shorter than the requested limit.
+    pub fn next(&self, page: Page) -> Option<Page> {
+        let offset = self.offset.unwrap_or(page.offset) + self.values.len() as u64;
+        let more = match self.total {
+            Some(total) => offset < total,
+            None => self.values.len() as u64 >= page.limit,
+        };
+        if self.values.is_empty() || !more {
+            None
+        } else {
+            Some(Page { offset, ..page })
+        }
+    }
+}
+
+
+impl<T: Render> Render for Paginated<T> {
+    fn columns() -> &'static [&'static str] { T::columns() }
+
+    fn rows(&self) -> Result<Vec<Value>> { output::rows(&self.values) }
+
+    fn is_list() -> bool { true }
+
+    fn id() -> &'static str { T::id() }
+}
+
+
+/// Iterate over each page of a list endpoint, fetching the next page on demand.
+pub struct Pages<'c, T, F> {
+    config: &'c mut Config,
+    fetch:  F,
+    next:   Option<Page>,
+    values: PhantomData<T>,
+}
+
+impl<'c, T, F> Pages<'c, T, F>
+where
+    F: FnMut(&mut Config, Option<Page>) -> Result<Paginated<T>>,
+{
+    pub fn new(config: &'c mut Config, first: Page, fetch: F) -> Self {
+        Pages {
+            config,
+            fetch,
+            next: Some(first),
+            values: PhantomData,
+        }
+    }
+}
+
+impl<'c, T, F> Iterator for Pages<'c, T, F>
+where
+    F: FnMut(&mut Config, Option<Page>) -> Result<Paginated<T>>,
+{
+    type Item = Result<Vec<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page = self.next.take()?;
+        debug!("fetching page at offset {} with limit {}", page.offset, page.limit);
+        match (self.fetch)(self.config, Some(page)) {
+            Ok(body) => {
+                self.next = body.next(page);
+                Some(Ok(body.values))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+
+/// Print the requested page, or stream every page with `--all-pages`.
+pub fn list_pages<'a, T, F>(config: &mut Config, args: &ArgMatches<'a>, out: &Output, mut fetch: F) -> Result<()>
+where
+    T: Render,
+    F: FnMut(&mut Config, Option<Page>) -> Result<Paginated<T>>,
+{
+    let page = Page::from_args(args)?;
+    if args.is_present("all_pages") {
+        out.print_pages(Pages::new(config, page.unwrap_or_default(), fetch))
+    } else {
+        out.print(&fetch(config, page)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+
+    #[test]
+    fn next_page_until_total() {
+        let page = Page { offset: 0, limit: 2 };
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [1, 2], "total": 5, "offset": 0, "limit": 2}"#).expect("page");
+        let page = body.next(page).expect("second page");
+        assert_eq!(page, Page { offset: 2, limit: 2 });
+
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [3, 4], "total": 5, "offset": 2, "limit": 2}"#).expect("page");
+        let page = body.next(page).expect("third page");
+        assert_eq!(page, Page { offset: 4, limit: 2 });
+
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [5], "total": 5, "offset": 4, "limit": 2}"#).expect("page");
+        assert_eq!(body.next(page), None);
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [], "total": 9, "offset": 6}"#).expect("page");
+        assert_eq!(body.next(page), None);
+    }
+
+    #[test]
+    fn next_page_without_total() {
+        let page = Page { offset: 0, limit: 2 };
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [1, 2]}"#).expect("page");
+        let page = body.next(page).expect("second page");
+        assert_eq!(page, Page { offset: 2, limit: 2 });
+
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [3, 4]}"#).expect("page");
+        let page = body.next(page).expect("third page");
+        assert_eq!(page, Page { offset: 4, limit: 2 });
+
+        let body: Paginated<u64> = serde_json::from_str(r#"{"values": [5]}"#).expect("page");
+        assert_eq!(body.next(page), None);
+    }
+}
@@ -1,31 +1,42 @@
 use clap::ArgMatches;
-use reqwest::{Client, Response};
+use serde_json::Value;
 use std::{
+    collections::BTreeMap,
     fmt::{self, Display, Formatter},
     str::FromStr,
 };
 use uuid::Uuid;
 
+use api::{
+    pagination::{list_pages, with_page, Page, Pages, Paginated},
+    resolve::{resolve, Named},
+};
 use config::Config;
 use error::{Error, Result};
 use http::{Http, HttpMethods};
+use jobs::{last_ok, run_all};
+use output::{Output, Render};
+use transport::Request;
 
 
 /// Available Device Registry API methods.
 pub trait RegistryApi {
-    fn create_device(&mut Config, name: &str, id: &str, kind: DeviceType) -> Result<Response>;
-    fn delete_device(&mut Config, device: Uuid) -> Result<Response>;
-    fn list_device(&mut Config, device: Uuid) -> Result<Response>;
-    fn list_all_devices(&mut Config) -> Result<Response>;
-
-    fn create_group(&mut Config, name: &str, group_type: GroupType) -> Result<Response>;
-    fn rename_group(&mut Config, group: Uuid, name: &str) -> Result<Response>;
-    fn add_to_group(&mut Config, group: Uuid, device: Uuid) -> Result<Response>;
-    fn remove_from_group(&mut Config, group: Uuid, device: Uuid) -> Result<Response>;
-
-    fn list_groups(&mut Config, device: Uuid) -> Result<Response>;
-    fn list_devices(&mut Config, group: Uuid) -> Result<Response>;
-    fn list_all_groups(&mut Config) -> Result<Response>;
+    fn create_device(&mut Config, name: &str, id: &str, kind: DeviceType) -> Result<Uuid>;
+    fn delete_device(&mut Config, device: Uuid) -> Result<()>;
+    fn list_device(&mut Config, device: Uuid) -> Result<Device>;
+    fn list_all_devices(&mut Config, page: Option<Page>) -> Result<Paginated<Device>>;
+    fn find_devices(&mut Config, name: &str, page: Option<Page>) -> Result<Paginated<Device>>;
+    fn find_devices_by_id(&mut Config, device_id: &str, page: Option<Page>) -> Result<Paginated<Device>>;
+
+    fn create_group(&mut Config, name: &str, group_type: GroupType) -> Result<Uuid>;
+    fn rename_group(&mut Config, group: Uuid, name: &str) -> Result<()>;
+    fn add_to_group(&mut Config, group: Uuid, device: Uuid) -> Result<()>;
+    fn remove_from_group(&mut Config, group: Uuid, device: Uuid) -> Result<()>;
+
+    fn list_groups(&mut Config, device: Uuid, page: Option<Page>) -> Result<Paginated<Uuid>>;
+    fn list_devices(&mut Config, group: Uuid, page: Option<Page>) -> Result<Paginated<Uuid>>;
+    fn list_all_groups(&mut Config, page: Option<Page>) -> Result<Paginated<Group>>;
+    fn find_groups(&mut Config, name: &str, page: Option<Page>) -> Result<Paginated<Group>>;
 }
 
 
@@ -34,103 +45,224 @@ pub struct Registry;
 
 impl<'a> Registry {
     /// Parse args as device listing preferences.
-    pub fn list_device_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
+    pub fn list_device_args(config: &mut Config, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match parse_list_args(args)? {
-            (true, _, _)         => Self::list_all_devices(config),
-            (_, Some(device), _) => Self::list_device(config, device),
-            (_, _, Some(group))  => Self::list_devices(config, group),
+        match parse_list_args(config, args)? {
+            (true, _, _)         => list_pages(config, args, out, Self::list_all_devices),
+            (_, Some(device), _) => out.print(&Self::list_device(config, device)?),
+            (_, _, Some(group))  => list_pages(config, args, out, |config, page| Self::list_devices(config, group, page)),
             _ => Err(Error::Args("one of --all, --device, or --group required".into())),
         }
     }
 
     /// Parse args as group listing preferences.
-    pub fn list_group_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
+    pub fn list_group_args(config: &mut Config, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match parse_list_args(args)? {
-            (true, _, _)         => Self::list_all_groups(config),
-            (_, Some(device), _) => Self::list_groups(config, device),
-            (_, _, Some(group))  => Self::list_devices(config, group),
+        match parse_list_args(config, args)? {
+            (true, _, _)         => list_pages(config, args, out, Self::list_all_groups),
+            (_, Some(device), _) => list_pages(config, args, out, |config, page| Self::list_groups(config, device, page)),
+            (_, _, Some(group))  => list_pages(config, args, out, |config, page| Self::list_devices(config, group, page)),
             _ => Err(Error::Args("one of --all, --device, or --group required".into())),
         }
     }
+
+    /// Find the device with this UUID, name or device ID.
+    pub fn device_id(config: &mut Config, device: &str) -> Result<Uuid> {
+        Self::device_ids(config, &[device]).map(|mut ids| ids.remove(0))
+    }
+
+    /// Find the devices with these UUIDs, names or device IDs.
+    ///
+    /// Only the devices whose name contains an arg or whose device ID is an
+    /// arg are fetched, using two filtered lists per arg.
+    pub fn device_ids(config: &mut Config, devices: &[&str]) -> Result<Vec<Uuid>> {
+        resolve(config, "device", devices, |config| {
+            let mut named = BTreeMap::new();
+            let finders: [fn(&mut Config, &str, Option<Page>) -> Result<Paginated<Device>>; 2] =
+                [Self::find_devices, Self::find_devices_by_id];
+            for device in devices.iter().filter(|device| device.parse::<Uuid>().is_err()) {
+                for find in &finders {
+                    for page in Pages::new(config, Page::default(), |config, page| find(config, device, page)) {
+                        for device in page? {
+                            let _ = named.insert(device.uuid, Named::new(device.uuid, vec![device.device_name, device.device_id]));
+                        }
+                    }
+                }
+            }
+            Ok(named.into_iter().map(|(_, named)| named).collect())
+        })
+    }
+
+    /// Find the group with this UUID or name.
+    pub fn group_id(config: &mut Config, group: &str) -> Result<Uuid> {
+        Self::group_ids(config, &[group]).map(|mut ids| ids.remove(0))
+    }
+
+    /// Find the groups with these UUIDs or names.
+    ///
+    /// Only the groups whose name contains an arg are fetched.
+    pub fn group_ids(config: &mut Config, groups: &[&str]) -> Result<Vec<Uuid>> {
+        resolve(config, "group", groups, |config| {
+            let mut named = BTreeMap::new();
+            for group in groups.iter().filter(|group| group.parse::<Uuid>().is_err()) {
+                for page in Pages::new(config, Page::default(), |config, page| Self::find_groups(config, group, page)) {
+                    for group in page? {
+                        let _ = named.insert(group.id, Named::new(group.id, vec![group.group_name]));
+                    }
+                }
+            }
+            Ok(named.into_iter().map(|(_, named)| named).collect())
+        })
+    }
+
+    /// Add each device to a group, with up to `jobs` requests in flight.
+    pub fn add_devices(config: &mut Config, group: Uuid, devices: Vec<Uuid>, jobs: usize) -> Result<()> {
+        last_ok(run_all(config, jobs, devices, |config, device| Self::add_to_group(config, group, device))?)
+    }
+
+    /// Remove each device from a group, with up to `jobs` requests in flight.
+    pub fn remove_devices(config: &mut Config, group: Uuid, devices: Vec<Uuid>, jobs: usize) -> Result<()> {
+        last_ok(run_all(config, jobs, devices, |config, device| Self::remove_from_group(config, group, device))?)
+    }
 }
 
 impl RegistryApi for Registry {
-    fn create_device(config: &mut Config, name: &str, id: &str, kind: DeviceType) -> Result<Response> {
+    fn create_device(config: &mut Config, name: &str, id: &str, kind: DeviceType) -> Result<Uuid> {
         debug!("creating device {} of type {} with id {}", name, kind, id);
-        let req = Client::new().post(&format!("{}api/v1/devices", config.registry)).query(&[
+        let req = Request::post(&format!("{}api/v1/devices", config.registry))?.query(&[
             ("deviceName", name),
             ("deviceId", id),
             ("deviceType", &format!("{}", kind)),
-        ]);
-        Http::send(req, config.token()?)
+        ])?;
+        Http::send(req, config)?.json()
     }
 
-    fn delete_device(config: &mut Config, device: Uuid) -> Result<Response> {
+    fn delete_device(config: &mut Config, device: Uuid) -> Result<()> {
         debug!("deleting device {}", device);
-        Http::delete(&format!("{}api/v1/devices/{}", config.registry, device), config.token()?)
+        Http::delete(&format!("{}api/v1/devices/{}", config.registry, device), config).map(|_| ())
     }
 
-    fn list_device(config: &mut Config, device: Uuid) -> Result<Response> {
+    fn list_device(config: &mut Config, device: Uuid) -> Result<Device> {
         debug!("listing details for device {}", device);
-        Http::get(&format!("{}api/v1/devices/{}", config.registry, device), config.token()?)
+        Http::get(&format!("{}api/v1/devices/{}", config.registry, device), config)?.json()
     }
 
-    fn list_all_devices(config: &mut Config) -> Result<Response> {
+    fn list_all_devices(config: &mut Config, page: Option<Page>) -> Result<Paginated<Device>> {
         debug!("listing all devices");
-        Http::get(&format!("{}api/v1/devices", config.registry), config.token()?)
+        let req = Request::get(&format!("{}api/v1/devices", config.registry))?;
+        Http::send(with_page(req, page)?, config)?.json()
+    }
+
+    fn find_devices(config: &mut Config, name: &str, page: Option<Page>) -> Result<Paginated<Device>> {
+        debug!("listing devices named like {}", name);
+        let req = Request::get(&format!("{}api/v1/devices", config.registry))?.query(&[("nameContains", name)])?;
+        Http::send(with_page(req, page)?, config)?.json()
     }
 
-    fn create_group(config: &mut Config, name: &str, group_type: GroupType) -> Result<Response> {
+    fn find_devices_by_id(config: &mut Config, device_id: &str, page: Option<Page>) -> Result<Paginated<Device>> {
+        debug!("listing devices with device id {}", device_id);
+        let req = Request::get(&format!("{}api/v1/devices", config.registry))?.query(&[("deviceId", device_id)])?;
+        Http::send(with_page(req, page)?, config)?.json()
+    }
+
+    fn create_group(config: &mut Config, name: &str, group_type: GroupType) -> Result<Uuid> {
         debug!("creating device group {}", name);
-        let req = Client::new()
-            .post(&format!("{}api/v1/device_groups", config.registry))
-            .json(&json!({"name": name, "groupType": format!("{}", group_type)}));
-        Http::send(req, config.token()?)
+        let req = Request::post(&format!("{}api/v1/device_groups", config.registry))?
+            .json(&json!({"name": name, "groupType": format!("{}", group_type)}))?;
+        Http::send(req, config)?.json()
     }
 
-    fn rename_group(config: &mut Config, group: Uuid, name: &str) -> Result<Response> {
+    fn rename_group(config: &mut Config, group: Uuid, name: &str) -> Result<()> {
         debug!("renaming group {} to {}", group, name);
-        let req = Client::new()
-            .put(&format!("{}api/v1/device_groups/{}/rename", config.registry, group))
-            .query(&[("groupId", &format!("{}", group), ("groupName", name))]);
-        Http::send(req, config.token()?)
+        let req = Request::put(&format!("{}api/v1/device_groups/{}/rename", config.registry, group))?
+            .query(&[("groupId", &format!("{}", group)), ("groupName", &name.to_string())])?;
+        Http::send(req, config).map(|_| ())
     }
 
-    fn add_to_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<Response> {
+    fn add_to_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<()> {
         debug!("adding device {} to group {}", device, group);
-        let req = Client::new()
-            .post(&format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))
-            .query(&[("deviceId", device), ("groupId", group)]);
-        Http::send(req, config.token()?)
+        let req = Request::post(&format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))?
+            .query(&[("deviceId", device), ("groupId", group)])?;
+        Http::send_repeatable(req, config).map(|_| ())
     }
 
-    fn remove_from_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<Response> {
+    fn remove_from_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<()> {
         debug!("removing device {} from group {}", device, group);
-        let req = Client::new()
-            .delete(&format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))
-            .query(&[("deviceId", format!("{}", device)), ("groupId", format!("{}", group))]);
-        Http::send(req, config.token()?)
+        let req = Request::delete(&format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))?
+            .query(&[("deviceId", format!("{}", device)), ("groupId", format!("{}", group))])?;
+        Http::send(req, config).map(|_| ())
     }
 
-    fn list_devices(config: &mut Config, group: Uuid) -> Result<Response> {
+    fn list_devices(config: &mut Config, group: Uuid, page: Option<Page>) -> Result<Paginated<Uuid>> {
         debug!("listing devices in group {}", group);
-        Http::get(
-            &format!("{}api/v1/device_groups/{}/devices", config.registry, group),
-            config.token()?,
-        )
+        let req = Request::get(&format!("{}api/v1/device_groups/{}/devices", config.registry, group))?;
+        Http::send(with_page(req, page)?, config)?.json()
     }
 
-    fn list_groups(config: &mut Config, device: Uuid) -> Result<Response> {
+    fn list_groups(config: &mut Config, device: Uuid, page: Option<Page>) -> Result<Paginated<Uuid>> {
         debug!("listing groups for device {}", device);
-        Http::get(&format!("{}api/v1/devices/{}/groups", config.registry, device), config.token()?)
+        let req = Request::get(&format!("{}api/v1/devices/{}/groups", config.registry, device))?;
+        Http::send(with_page(req, page)?, config)?.json()
     }
 
-    fn list_all_groups(config: &mut Config) -> Result<Response> {
+    fn list_all_groups(config: &mut Config, page: Option<Page>) -> Result<Paginated<Group>> {
         debug!("listing all groups");
-        Http::get(&format!("{}api/v1/device_groups", config.registry), config.token()?)
+        let req = Request::get(&format!("{}api/v1/device_groups", config.registry))?;
+        Http::send(with_page(req, page)?, config)?.json()
     }
+
+    fn find_groups(config: &mut Config, name: &str, page: Option<Page>) -> Result<Paginated<Group>> {
+        debug!("listing groups named like {}", name);
+        let req = Request::get(&format!("{}api/v1/device_groups", config.registry))?.query(&[("nameContains", name)])?;
+        Http::send(with_page(req, page)?, config)?.json()
+    }
+}
+
+
+/// A device registered in the Device Registry.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    pub uuid:          Uuid,
+    pub device_name:   String,
+    pub device_id:     String,
+    pub device_type:   String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen:     Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at:    Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activated_at:  Option<String>,
+    /// Any other fields returned by the server.
+    #[serde(flatten)]
+    pub extra:         BTreeMap<String, Value>,
+}
+
+impl Render for Device {
+    fn columns() -> &'static [&'static str] { &["uuid", "deviceName", "deviceId", "deviceStatus", "lastSeen"] }
+
+    fn id() -> &'static str { "uuid" }
+}
+
+/// A group of devices.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Group {
+    pub id:         Uuid,
+    pub group_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// Any other fields returned by the server.
+    #[serde(flatten)]
+    pub extra:      BTreeMap<String, Value>,
+}
+
+impl Render for Group {
+    fn columns() -> &'static [&'static str] { &["id", "groupName", "groupType", "createdAt"] }
 }
 
 
@@ -212,17 +344,155 @@ impl Display for GroupType {
 
 
 /// Parse into a tuple of --all, --device, and --group arg values.
-fn parse_list_args<'a>(args: &ArgMatches<'a>) -> Result<(bool, Option<Uuid>, Option<Uuid>)> {
+fn parse_list_args<'a>(config: &mut Config, args: &ArgMatches<'a>) -> Result<(bool, Option<Uuid>, Option<Uuid>)> {
     let all = args.is_present("all");
     let device = if let Some(val) = args.value_of("device") {
-        Some(val.parse()?)
+        Some(Registry::device_id(config, val)?)
     } else {
         None
     };
     let group = if let Some(val) = args.value_of("group") {
-        Some(val.parse()?)
+        Some(Registry::group_id(config, val)?)
     } else {
         None
     };
     Ok((all, device, group))
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{Method, StatusCode};
+    use serde_json;
+    use std::{path::PathBuf, sync::Arc};
+    use transport::{DryRun, FakeTransport, Response};
+
+
+    fn fake_config() -> (Config, FakeTransport) {
+        let fake = FakeTransport::new();
+        let mut config = Config::for_test(&"https://registry.example.com/".parse().expect("url"), PathBuf::new());
+        config.static_token = true;
+        config.transport = Some(Arc::new(fake.clone()));
+        (config, fake)
+    }
+
+    #[test]
+    fn rename_group_query() {
+        let (mut config, fake) = fake_config();
+        let _ = fake.respond(Response::new(StatusCode::OK, ""));
+        let group = Uuid::new_v4();
+        Registry::rename_group(&mut config, group, "new name").expect("rename");
+
+        let req = &fake.requests()[0];
+        assert_eq!(req.method, Method::PUT);
+        assert_eq!(req.url.path(), format!("/api/v1/device_groups/{}/rename", group));
+        assert_eq!(req.query_pairs(), vec![
+            ("groupId".to_string(), group.to_string()),
+            ("groupName".to_string(), "new name".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn add_to_group_query() {
+        let (mut config, fake) = fake_config();
+        let _ = fake.respond(Response::new(StatusCode::OK, ""));
+        let (group, device) = (Uuid::new_v4(), Uuid::new_v4());
+        Registry::add_to_group(&mut config, group, device).expect("add");
+
+        let req = &fake.requests()[0];
+        assert_eq!(req.method, Method::POST);
+        assert_eq!(req.url.path(), format!("/api/v1/device_groups/{}/devices/{}", group, device));
+        assert_eq!(req.query_pairs(), vec![
+            ("deviceId".to_string(), device.to_string()),
+            ("groupId".to_string(), group.to_string()),
+        ]);
+        assert!(req.body.is_none());
+    }
+
+    #[test]
+    fn list_devices_keeps_extra_fields() {
+        let (mut config, fake) = fake_config();
+        let _ = fake.respond(Response::new(StatusCode::OK, r#"{
+            "values": [{
+                "uuid": "2a1b5b59-1d5b-4d5a-8f1e-1c8b6a1f2e3d",
+                "deviceName": "bench-1",
+                "deviceId": "VIN123",
+                "deviceType": "Vehicle",
+                "deviceStatus": "UpToDate",
+                "lastSeen": null,
+                "namespace": "default"
+            }],
+            "total": 1,
+            "offset": 0,
+            "limit": 50
+        }"#));
+        let devices = Registry::list_all_devices(&mut config, None).expect("devices");
+
+        assert_eq!(devices.total, Some(1));
+        let device = &devices.values[0];
+        assert_eq!(device.device_name, "bench-1");
+        assert_eq!(device.device_status.as_deref(), Some("UpToDate"));
+        assert!(device.last_seen.is_none());
+        assert_eq!(device.extra["namespace"], "default");
+        let json = serde_json::to_value(device).expect("json");
+        assert_eq!(json["deviceId"], "VIN123");
+        assert_eq!(json["namespace"], "default");
+        assert!(json.get("lastSeen").is_none());
+    }
+
+    #[test]
+    fn device_ids_by_name_or_device_id() {
+        let (mut config, fake) = fake_config();
+        let page = |devices: &str| {
+            Response::new(StatusCode::OK, format!(r#"{{"values": [{}], "total": 1, "offset": 0, "limit": 100}}"#, devices))
+        };
+        let bench_1 = r#"{"uuid": "2a1b5b59-1d5b-4d5a-8f1e-1c8b6a1f2e3d", "deviceName": "bench-1",
+                          "deviceId": "VIN1", "deviceType": "Vehicle"}"#;
+        let bench_2 = r#"{"uuid": "3b2c6c6a-1d5b-4d5a-8f1e-1c8b6a1f2e3d", "deviceName": "bench-2",
+                          "deviceId": "VIN2", "deviceType": "Vehicle"}"#;
+        let _ = fake.respond(page("")).respond(page(bench_2)).respond(page(bench_1)).respond(page(bench_1));
+        let other = Uuid::new_v4();
+        let ids = Registry::device_ids(&mut config, &["VIN2", "bench-1", &other.to_string()]).expect("ids");
+
+        assert_eq!(ids, vec![
+            "3b2c6c6a-1d5b-4d5a-8f1e-1c8b6a1f2e3d".parse().expect("uuid"),
+            "2a1b5b59-1d5b-4d5a-8f1e-1c8b6a1f2e3d".parse().expect("uuid"),
+            other,
+        ]);
+        let queries = fake
+            .requests()
+            .iter()
+            .map(|req| format!("{}?{}", req.url.path(), req.url.query().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        assert_eq!(queries, vec![
+            "/api/v1/devices?nameContains=VIN2&offset=0&limit=100",
+            "/api/v1/devices?deviceId=VIN2&offset=0&limit=100",
+            "/api/v1/devices?nameContains=bench-1&offset=0&limit=100",
+            "/api/v1/devices?deviceId=bench-1&offset=0&limit=100",
+        ]);
+    }
+
+    #[test]
+    fn dry_run_looks_up_names() {
+        let (mut config, fake) = fake_config();
+        let _ = fake.respond(Response::new(StatusCode::OK, r#"{
+            "values": [
+                {"uuid": "2a1b5b59-1d5b-4d5a-8f1e-1c8b6a1f2e3d", "deviceName": "bench-1", "deviceId": "VIN1", "deviceType": "Vehicle"}
+            ],
+            "total": 1,
+            "offset": 0,
+            "limit": 100
+        }"#));
+        let _ = fake.respond(Response::new(StatusCode::OK, r#"{"values": [], "total": 0, "offset": 0, "limit": 100}"#));
+        config.dry_run = Some(DryRun::default());
+        let device = Registry::device_id(&mut config, "bench-1").expect("lookup");
+        Registry::delete_device(&mut config, device).expect("dry run delete");
+
+        assert_eq!(device, "2a1b5b59-1d5b-4d5a-8f1e-1c8b6a1f2e3d".parse().expect("uuid"));
+        assert_eq!(config.dry_run, Some(DryRun::default()));
+        let requests = fake.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|req| req.method == Method::GET));
+    }
+}
@@ -1,6 +1,10 @@
 use clap::ArgMatches;
-use reqwest::{multipart::Form, Client, Response};
-use std::{collections::HashMap, fs, path::Path};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
 use toml;
 use url::Url;
 use url_serde;
@@ -10,57 +14,60 @@ use api::director::TargetFormat;
 use config::Config;
 use error::{Error, Result};
 use http::{Http, HttpMethods};
+use jobs::{last_ok, run_all};
+use output::Render;
+use transport::{Part, Request};
 
 
 /// Available TUF Reposerver API methods.
 pub trait ReposerverApi {
-    fn add_package(&mut Config, package: TufPackage) -> Result<Response>;
-    fn get_package(&mut Config, name: &str, version: &str) -> Result<Response>;
+    fn add_package(&mut Config, package: TufPackage) -> Result<()>;
+    fn get_package(&mut Config, name: &str, version: &str) -> Result<Vec<u8>>;
+    fn list_packages(&mut Config) -> Result<Vec<PackageInfo>>;
 }
 
 /// Make API calls to the TUF Reposerver.
 pub struct Reposerver;
 
 impl ReposerverApi for Reposerver {
-    fn add_package(config: &mut Config, package: TufPackage) -> Result<Response> {
+    fn add_package(config: &mut Config, package: TufPackage) -> Result<()> {
         let entry = format!("{}_{}", package.name, package.version);
         debug!("adding package with entry name {}", entry);
-        let req = Client::new()
-            .put(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry))
+        let req = Request::put(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry))?
             .query(&[
                 ("name", urlencoding::encode(&package.name)),
                 ("version", urlencoding::encode(&package.version)),
                 ("hardwareIds", package.hardware.join(",")),
                 ("targetFormat", format!("{}", package.format)),
-            ])
-            .multipart(match package.target {
-                RepoTarget::Path(path) => Form::new().file("file", path)?,
-                RepoTarget::Url(url) => Form::new().file("fileUri", url.as_str())?,
-            });
-        Http::send(req, config.token()?)
+            ])?
+            .multipart(vec![match package.target {
+                RepoTarget::Path(path) => Part::file("file", path),
+                RepoTarget::Url(url) => Part::file("fileUri", url.as_str()),
+            }]);
+        Http::send(req, config).map(|_| ())
     }
 
-    fn get_package(config: &mut Config, name: &str, version: &str) -> Result<Response> {
+    fn get_package(config: &mut Config, name: &str, version: &str) -> Result<Vec<u8>> {
         let entry = format!("{}_{}", name, version);
         debug!("fetching package with entry name {}", entry);
-        Http::get(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry), config.token()?)
+        Http::get(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry), config).map(|resp| resp.body)
+    }
+
+    fn list_packages(config: &mut Config) -> Result<Vec<PackageInfo>> {
+        debug!("fetching the targets metadata");
+        let targets = Http::get(&format!("{}api/v1/user_repo/targets.json", config.reposerver), config)?
+            .json::<SignedMetadata<TargetsMetadata>>()?;
+        Ok(targets.signed.targets.into_iter().map(PackageInfo::from).collect())
     }
 }
 
 impl Reposerver {
-    /// Upload multiple packages (without batching), returning the final response.
-    pub fn add_packages(config: &mut Config, packages: TufPackages) -> Result<Response> {
-        let mut responses = packages
-            .packages
-            .into_iter()
-            .map(|package| Self::add_package(config, package))
-            .collect::<Result<Vec<_>>>()?;
-        let last = responses.len() - 1;
-        Ok(responses.remove(last))
+    /// Upload multiple packages with up to `jobs` uploads in flight.
+    pub fn add_packages(config: &mut Config, packages: TufPackages, jobs: usize) -> Result<()> {
+        last_ok(run_all(config, jobs, packages.packages, Self::add_package)?)
     }
 }
 
-
 /// Parsed TOML package metadata.
 #[derive(Serialize, Deserialize)]
 pub struct PackageMetadata {
@@ -154,6 +161,90 @@ impl TufPackages {
     }
 }
 
+/// Signed TUF metadata from the Reposerver.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedMetadata<T> {
+    pub signed: T,
+}
+
+/// The TUF targets metadata listing every package in the repository.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TargetsMetadata {
+    pub version: u64,
+    pub expires: String,
+    pub targets: BTreeMap<String, TargetItem>,
+}
+
+/// The TUF metadata for a single target file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TargetItem {
+    pub length: u64,
+    pub hashes: BTreeMap<String, String>,
+    #[serde(default)]
+    pub custom: Option<TargetCustom>,
+}
+
+/// The package details the Reposerver adds to each target.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetCustom {
+    pub name:          Option<String>,
+    pub version:       Option<String>,
+    #[serde(default)]
+    pub hardware_ids:  Vec<String>,
+    pub target_format: Option<TargetFormat>,
+    pub uri:           Option<String>,
+    pub created_at:    Option<String>,
+    pub updated_at:    Option<String>,
+    /// Any other fields returned by the server.
+    #[serde(flatten)]
+    pub extra:         BTreeMap<String, Value>,
+}
+
+/// A summary of a package in the TUF Reposerver.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageInfo {
+    pub filename:      String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name:          Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version:       Option<String>,
+    pub hardware_ids:  Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_format: Option<TargetFormat>,
+    pub length:        u64,
+    pub hashes:        BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri:           Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at:    Option<String>,
+}
+
+impl Render for PackageInfo {
+    fn columns() -> &'static [&'static str] { &["name", "version", "targetFormat", "hardwareIds", "length", "createdAt"] }
+
+    fn id() -> &'static str { "filename" }
+}
+
+impl From<(String, TargetItem)> for PackageInfo {
+    fn from((filename, item): (String, TargetItem)) -> Self {
+        let custom = item.custom.unwrap_or_default();
+        PackageInfo {
+            filename,
+            name:          custom.name,
+            version:       custom.version,
+            hardware_ids:  custom.hardware_ids,
+            target_format: custom.target_format,
+            length:        item.length,
+            hashes:        item.hashes,
+            uri:           custom.uri,
+            created_at:    custom.created_at,
+        }
+    }
+}
+
+
 /// Target data pointed to by either filesystem path or remote URL.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
 pub enum RepoTarget {
@@ -0,0 +1,105 @@
+use uuid::Uuid;
+
+use config::Config;
+use error::{Error, Result};
+
+
+/// A resource that can be referred to by any of its names.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Named {
+    pub id:    Uuid,
+    pub names: Vec<String>,
+}
+
+impl Named {
+    pub fn new(id: Uuid, names: Vec<String>) -> Self { Named { id, names } }
+}
+
+
+/// Parse each arg as a UUID, or else find the one resource it names.
+///
+/// The candidates are only fetched when some arg is not a UUID. They are
+/// fetched even in a dry run, as with `--allow-gets`, so that the requests
+/// using the resolved IDs can still be printed.
+pub fn resolve<F>(config: &mut Config, kind: &str, args: &[&str], fetch: F) -> Result<Vec<Uuid>>
+where
+    F: FnOnce(&mut Config) -> Result<Vec<Named>>,
+{
+    if let Ok(ids) = args.iter().map(|arg| arg.parse()).collect::<::std::result::Result<_, _>>() {
+        return Ok(ids);
+    }
+    debug!("looking up {} names: {:?}", kind, args);
+    let dry_run = config.dry_run;
+    if let Some(ref mut options) = config.dry_run {
+        options.allow_gets = true;
+    }
+    let candidates = fetch(config);
+    config.dry_run = dry_run;
+    let candidates = candidates?;
+    args.iter().map(|arg| pick(kind, arg, &candidates)).collect()
+}
+
+/// Find the single candidate that matches the arg.
+fn pick(kind: &str, arg: &str, candidates: &[Named]) -> Result<Uuid> {
+    if let Ok(id) = arg.parse() {
+        return Ok(id);
+    }
+    let found = candidates
+        .iter()
+        .filter(|named| named.names.iter().any(|name| name == arg))
+        .collect::<Vec<_>>();
+    match found.as_slice() {
+        [named] => Ok(named.id),
+        [] => Err(Error::Args(format!("no {} with the UUID or name `{}`", kind, arg))),
+        _ => Err(Error::Args(format!(
+            "`{}` matches {} {}s, use one of these UUIDs instead: {}",
+            arg,
+            found.len(),
+            kind,
+            found
+                .iter()
+                .map(|named| format!("{} ({})", named.id, named.names.join(", ")))
+                .collect::<Vec<_>>()
+                .join("; ")
+        ))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn groups() -> Vec<Named> {
+        vec![
+            Named::new("11111111-1d5b-4d5a-8f1e-1c8b6a1f2e3d".parse().expect("uuid"), vec!["bench".into()]),
+            Named::new("22222222-1d5b-4d5a-8f1e-1c8b6a1f2e3d".parse().expect("uuid"), vec!["fleet".into()]),
+            Named::new("33333333-1d5b-4d5a-8f1e-1c8b6a1f2e3d".parse().expect("uuid"), vec!["fleet".into()]),
+        ]
+    }
+
+    #[test]
+    fn pick_by_uuid_or_name() {
+        let groups = groups();
+        let other = Uuid::new_v4();
+        assert_eq!(pick("group", &other.to_string(), &groups).expect("uuid"), other);
+        assert_eq!(pick("group", "bench", &groups).expect("name"), groups[0].id);
+        match pick("group", "lab", &groups) {
+            Err(Error::Args(err)) => assert_eq!(err, "no group with the UUID or name `lab`"),
+            _ => panic!("expected an unknown name"),
+        }
+    }
+
+    #[test]
+    fn ambiguous_names_list_candidates() {
+        match pick("group", "fleet", &groups()) {
+            Err(Error::Args(err)) => assert_eq!(
+                err,
+                "`fleet` matches 2 groups, use one of these UUIDs instead: \
+                 22222222-1d5b-4d5a-8f1e-1c8b6a1f2e3d (fleet); 33333333-1d5b-4d5a-8f1e-1c8b6a1f2e3d (fleet)"
+            ),
+            _ => panic!("expected an ambiguous name"),
+        }
+    }
+}
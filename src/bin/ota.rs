@@ -6,14 +6,22 @@ extern crate pretty_env_logger;
 
 use clap::{AppSettings, ArgMatches};
 use log::LevelFilter;
+use std::process;
 
 use ota::{
     command::{Command, Exec},
     error::Result,
-    http::Http,
+    output::Output,
 };
 
-fn main() -> Result<()> {
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
     let args = parse_args();
     pretty_env_logger::formatted_builder()
         .filter(None, LevelFilter::max())
@@ -21,10 +29,11 @@ fn main() -> Result<()> {
         .parse(args.value_of("level").unwrap_or("info"))
         .init();
 
+    let out = Output::from_args(&args)?;
     let (cmd, args) = args.subcommand();
     let cmd = cmd.parse::<Command>()?;
     let args = args.expect("cli args");
-    cmd.exec(args, Http::print_response)
+    cmd.exec(args, &out)
 }
 
 fn parse_args<'a>() -> ArgMatches<'a> {
@@ -37,6 +46,24 @@ fn parse_args<'a>() -> ArgMatches<'a> {
       (setting: AppSettings::UnifiedHelpMessage)
 
       (@arg level: -l --level [level] +global "Set the logging level")
+      (@arg profile: --profile [name] +global "Use this config profile")
+      (@arg credentials_zip: --("credentials-zip") [zip] +global "Override the credentials.zip path")
+      (@arg token: --token [token] +global "Override the access token")
+      (@arg namespace: --namespace [name] +global "Select a namespace from the access token")
+      (@arg campaigner_url: --("campaigner-url") [url] +global "Override the Campaigner URL")
+      (@arg director_url: --("director-url") [url] +global "Override the Director URL")
+      (@arg registry_url: --("registry-url") [url] +global "Override the Device Registry URL")
+      (@arg reposerver_url: --("reposerver-url") [url] +global "Override the TUF Reposerver URL")
+      (@arg record: --record [dir] +global conflicts_with[replay] "Record each request and response to this directory")
+      (@arg replay: --replay [dir] +global "Replay recorded responses from this directory")
+      (@arg trace_file: --("trace-file") [har] +global "Write each request and response to this HAR file")
+      (@arg dry_run: --("dry-run") +global "Print each request instead of sending it")
+      (@arg curl: --curl +global requires[dry_run] "Print dry-run requests as curl commands")
+      (@arg allow_gets: --("allow-gets") +global requires[dry_run] "Send GET requests during a dry run")
+      (@arg output: --output [format] +global "Print results as json (default), yaml, csv, table or ndjson")
+      (@arg fields: --fields [fields] +global "Print only these comma-separated fields of each item")
+      (@arg query: --query [expr] +global "Filter the result with a JMESPath-style expression")
+      (@arg quiet: -q --quiet +global conflicts_with[output fields query] "Print only the IDs of created or listed items")
 
       (@subcommand init =>
         (about: "Set config values before starting")
@@ -44,9 +71,91 @@ fn parse_args<'a>() -> ArgMatches<'a> {
         (setting: AppSettings::DeriveDisplayOrder)
         (setting: AppSettings::UnifiedHelpMessage)
         (@arg credentials: -z --credentials <zip> "Path to credentials.zip")
-        (@arg campaigner: -c --campaigner <url> "Campaigner URL")
-        (@arg director: -d --director <url> "Director URL")
-        (@arg registry: -r --registry <url> "Device Registry URL")
+        (@arg campaigner: -c --campaigner [url] "Campaigner URL")
+        (@arg director: -d --director [url] "Director URL")
+        (@arg registry: -r --registry [url] "Device Registry URL")
+        (@arg reposerver: -t --reposerver [url] "TUF Reposerver URL")
+      )
+
+      (@subcommand profile =>
+        (about: "Manage config profiles")
+        (setting: AppSettings::SubcommandRequiredElseHelp)
+        (setting: AppSettings::DeriveDisplayOrder)
+        (setting: AppSettings::InferSubcommands)
+        (setting: AppSettings::UnifiedHelpMessage)
+
+        (@subcommand list =>
+          (about: "List the available profiles")
+          (setting: AppSettings::UnifiedHelpMessage)
+        )
+
+        (@subcommand use =>
+          (about: "Set the default profile")
+          (setting: AppSettings::ArgRequiredElseHelp)
+          (setting: AppSettings::UnifiedHelpMessage)
+          (@arg name: +required "The profile name")
+        )
+
+        (@subcommand remove =>
+          (about: "Remove an existing profile")
+          (setting: AppSettings::ArgRequiredElseHelp)
+          (setting: AppSettings::UnifiedHelpMessage)
+          (@arg name: +required "The profile name")
+        )
+      )
+
+      (@subcommand config =>
+        (about: "Show, change and validate the current config")
+        (setting: AppSettings::SubcommandRequiredElseHelp)
+        (setting: AppSettings::DeriveDisplayOrder)
+        (setting: AppSettings::InferSubcommands)
+        (setting: AppSettings::UnifiedHelpMessage)
+
+        (@subcommand show =>
+          (about: "Print the config with secrets redacted")
+          (setting: AppSettings::UnifiedHelpMessage)
+        )
+
+        (@subcommand set =>
+          (about: "Change a config value")
+          (setting: AppSettings::ArgRequiredElseHelp)
+          (setting: AppSettings::UnifiedHelpMessage)
+          (@arg key: +required "The config key (e.g. director)")
+          (@arg value: +required "The new value")
+        )
+
+        (@subcommand validate =>
+          (about: "Check the credentials and each server endpoint")
+          (setting: AppSettings::UnifiedHelpMessage)
+        )
+      )
+
+      (@subcommand auth =>
+        (about: "Inspect the access token")
+        (setting: AppSettings::SubcommandRequiredElseHelp)
+        (setting: AppSettings::DeriveDisplayOrder)
+        (setting: AppSettings::InferSubcommands)
+        (setting: AppSettings::UnifiedHelpMessage)
+
+        (@subcommand namespaces =>
+          (about: "List the namespaces available to the access token")
+          (setting: AppSettings::UnifiedHelpMessage)
+        )
+      )
+
+      (@subcommand credentials =>
+        (about: "Audit credentials.zip files")
+        (setting: AppSettings::SubcommandRequiredElseHelp)
+        (setting: AppSettings::DeriveDisplayOrder)
+        (setting: AppSettings::InferSubcommands)
+        (setting: AppSettings::UnifiedHelpMessage)
+
+        (@subcommand inspect =>
+          (about: "List and check the contents of a credentials.zip file")
+          (setting: AppSettings::UnifiedHelpMessage)
+          (@arg zip: "Path to credentials.zip (defaults to the profile's)")
+          (@arg json: -j --json "Print the result as JSON")
+        )
       )
 
       (@subcommand campaign =>
@@ -61,8 +170,11 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::UnifiedHelpMessage)
           (@arg all: -a --all conflicts_with[campaign stats] "List all campaigns")
-          (@arg campaign: -c --campaign [uuid] conflicts_with[all] "The campaign id")
+          (@arg campaign: -c --campaign [campaign] conflicts_with[all] "The campaign UUID or name (a name fetches each matching campaign)")
           (@arg stats: -s --stats conflicts_with[all] "List campaign stats")
+          (@arg limit: --limit [n] "The maximum number of results per page")
+          (@arg offset: --offset [n] "The number of results to skip")
+          (@arg all_pages: --("all-pages") "Fetch and merge every page of results")
         )
 
         (@subcommand create =>
@@ -72,21 +184,21 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::UnifiedHelpMessage)
           (@arg update: -u --update <uuid> "Multi-target update id")
           (@arg name: -n --name <name> "A campaign name")
-          (@arg groups: -g --groups <uuid> ... "Apply the campaign to these groups")
+          (@arg groups: -g --groups <group> ... "Apply the campaign to these groups, by UUID or name")
         )
 
         (@subcommand launch =>
           (about: "Launch a created campaign")
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::UnifiedHelpMessage)
-          (@arg campaign: -c --campaign <uuid> "The campaign id")
+          (@arg campaign: -c --campaign <campaign> "The campaign UUID or name (a name fetches each matching campaign)")
         )
 
         (@subcommand cancel =>
           (about: "Cancel a launched campaign")
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::UnifiedHelpMessage)
-          (@arg campaign: -c --campaign <uuid> "The campaign id")
+          (@arg campaign: -c --campaign <campaign> "The campaign UUID or name (a name fetches each matching campaign)")
         )
       )
 
@@ -102,8 +214,11 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::UnifiedHelpMessage)
           (@arg all: -a --all conflicts_with[device] "List all devices")
-          (@arg device: -d --device [uuid] conflicts_with[group all] "List information about this device")
-          (@arg group: -g --group [uuid] conflicts_with[device all] "List the devices in this group")
+          (@arg device: -d --device [device] conflicts_with[group all] "List information about this device, by UUID, name or device ID")
+          (@arg group: -g --group [group] conflicts_with[device all] "List the devices in this group, by UUID or name")
+          (@arg limit: --limit [n] "The maximum number of results per page")
+          (@arg offset: --offset [n] "The number of results to skip")
+          (@arg all_pages: --("all-pages") "Fetch and merge every page of results")
         )
 
        /*
@@ -123,7 +238,7 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (about: "Delete an existing device")
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::UnifiedHelpMessage)
-          (@arg device: -d --device <uuid> "The device id")
+          (@arg device: -d --device <device> "The device UUID, name or device ID")
         )
       )
 
@@ -140,8 +255,11 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::DeriveDisplayOrder)
           (setting: AppSettings::UnifiedHelpMessage)
           (@arg all: -a --all conflicts_with[group device] "List all groups")
-          (@arg group: -g --group [uuid] conflicts_with[device all] "List the devices in this group")
-          (@arg device: -d --device [uuid] conflicts_with[group all] "List the groups for this device")
+          (@arg group: -g --group [group] conflicts_with[device all] "List the devices in this group, by UUID or name")
+          (@arg device: -d --device [device] conflicts_with[group all] "List the groups for this device, by UUID, name or device ID")
+          (@arg limit: --limit [n] "The maximum number of results per page")
+          (@arg offset: --offset [n] "The number of results to skip")
+          (@arg all_pages: --("all-pages") "Fetch and merge every page of results")
         )
 
         (@subcommand create =>
@@ -152,21 +270,23 @@ fn parse_args<'a>() -> ArgMatches<'a> {
         )
 
         (@subcommand add =>
-          (about: "Add a device to a group")
+          (about: "Add devices to a group")
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::DeriveDisplayOrder)
           (setting: AppSettings::UnifiedHelpMessage)
-          (@arg group: -g --group <uuid> "The group to add the device to")
-          (@arg device: -d --device <uuid> "The device to add")
+          (@arg group: -g --group <group> "The group to add the devices to, by UUID or name")
+          (@arg device: -d --device <device> ... "The devices to add, by UUID, name or device ID")
+          (@arg jobs: -j --jobs [n] "The number of requests to send at once")
         )
 
         (@subcommand remove =>
-          (about: "Remove a device from a group")
+          (about: "Remove devices from a group")
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::DeriveDisplayOrder)
           (setting: AppSettings::UnifiedHelpMessage)
-          (@arg group: -g --group <uuid> "The group to remove the device from")
-          (@arg device: -d --device <uuid> "The device to remove")
+          (@arg group: -g --group <group> "The group to remove the devices from, by UUID or name")
+          (@arg device: -d --device <device> ... "The devices to remove, by UUID, name or device ID")
+          (@arg jobs: -j --jobs [n] "The number of requests to send at once")
         )
 
         (@subcommand rename =>
@@ -174,7 +294,7 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::DeriveDisplayOrder)
           (setting: AppSettings::UnifiedHelpMessage)
-          (@arg group: -g --group <uuid> "The group to rename")
+          (@arg group: -g --group <group> "The group to rename, by UUID or name")
           (@arg name: -n --name <name> "The new group name")
         )
       )
@@ -186,11 +306,10 @@ fn parse_args<'a>() -> ArgMatches<'a> {
         (setting: AppSettings::InferSubcommands)
         (setting: AppSettings::UnifiedHelpMessage)
 
-       /*
         (@subcommand list =>
           (about: "List available packages")
+          (setting: AppSettings::UnifiedHelpMessage)
         )
-       */
 
         (@subcommand add =>
           (about: "Add a single package")
@@ -220,6 +339,7 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::DeriveDisplayOrder)
           (setting: AppSettings::UnifiedHelpMessage)
           (@arg packages: -p --packages <toml> "Package metadata file")
+          (@arg jobs: -j --jobs [n] "The number of uploads to send at once")
         )
       )
 
@@ -242,7 +362,7 @@ fn parse_args<'a>() -> ArgMatches<'a> {
           (setting: AppSettings::ArgRequiredElseHelp)
           (setting: AppSettings::UnifiedHelpMessage)
           (@arg update: -u --update <uuid> "Multi-target update id")
-          (@arg device: -d --device <uuid> "Apply to this device")
+          (@arg device: -d --device <device> "Apply to this device, by UUID, name or device ID")
         )
       )
     )
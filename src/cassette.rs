@@ -0,0 +1,230 @@
+use reqwest::StatusCode;
+use serde_json::{self, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
+};
+
+use error::{Error, Result};
+use transport::{redact_headers, scrub_form, scrub_json, Body, Request, Response, Transport};
+
+
+/// A recorded request and the response it received.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Interaction {
+    pub request:  RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedRequest {
+    pub method:  String,
+    pub url:     String,
+    pub headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body:    Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedResponse {
+    pub status:  u16,
+    pub headers: Vec<(String, String)>,
+    /// The JSON response body, or else the body as a string.
+    pub body:    Value,
+}
+
+impl Interaction {
+    /// Record the exchange with any tokens and secrets scrubbed.
+    pub fn new(req: &Request, resp: &Response) -> Self {
+        let body = req.body.as_ref().map(|body| match body {
+            Body::Json(json) => scrub_json(json.clone()),
+            Body::Form(form) => Value::String(scrub_form(form)),
+            Body::Multipart(parts) => json!(parts
+                .iter()
+                .map(|part| json!({"name": part.name, "file": part.path}))
+                .collect::<Vec<_>>()),
+        });
+        let resp_body = match serde_json::from_slice(&resp.body) {
+            Ok(json) => scrub_json(json),
+            Err(_) => Value::String(resp.text()),
+        };
+
+        Interaction {
+            request:  RecordedRequest {
+                method: req.method.to_string(),
+                url: req.url.to_string(),
+                headers: redact_headers(&req.headers),
+                body,
+            },
+            response: RecordedResponse {
+                status:  resp.status.as_u16(),
+                headers: redact_headers(&resp.headers),
+                body:    resp_body,
+            },
+        }
+    }
+
+    /// Whether the recorded request has the same method and URL.
+    pub fn matches(&self, req: &Request) -> bool { self.request.method == req.method.as_str() && self.request.url == req.url.as_str() }
+
+    /// Rebuild the recorded response.
+    pub fn response(&self) -> Result<Response> {
+        let body = match self.response.body {
+            Value::String(ref text) => text.clone().into_bytes(),
+            ref json => serde_json::to_vec(json)?,
+        };
+        Ok(Response {
+            status: StatusCode::from_u16(self.response.status)
+                .map_err(|_| Error::Parse(format!("invalid recorded status: {}", self.response.status)))?,
+            headers: self.response.headers.clone(),
+            body,
+        })
+    }
+}
+
+
+/// Save every interaction through the inner transport as cassette files.
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    dir:   PathBuf,
+    count: AtomicUsize,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Arc<dyn Transport>, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let count = cassette_files(&dir)?.len();
+        Ok(RecordingTransport {
+            inner,
+            dir,
+            count: AtomicUsize::new(count),
+        })
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        let resp = self.inner.execute(req)?;
+        let index = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let path = self.dir.join(format!("{:04}-{}.json", index, req.method.as_str().to_lowercase()));
+        debug!("recording {} {} to {:?}", req.method, req.url, path);
+        fs::write(&path, serde_json::to_vec_pretty(&Interaction::new(req, &resp))?)?;
+        Ok(resp)
+    }
+}
+
+
+/// Serve recorded responses without network access.
+///
+/// Each request is answered by the first unused interaction with the same
+/// method and URL.
+pub struct ReplayTransport {
+    interactions: Mutex<Vec<Option<Interaction>>>,
+}
+
+impl ReplayTransport {
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        ReplayTransport {
+            interactions: Mutex::new(interactions.into_iter().map(Some).collect()),
+        }
+    }
+
+    /// Load the cassette files from a directory, in recorded order.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let interactions = cassette_files(dir.as_ref())?
+            .into_iter()
+            .map(|path| Ok(serde_json::from_slice(&fs::read(path)?)?))
+            .collect::<Result<Vec<_>>>()?;
+        debug!("replaying {} interactions from {:?}", interactions.len(), dir.as_ref());
+        Ok(Self::new(interactions))
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        let mut interactions = self.interactions.lock().expect("replay lock");
+        let next = interactions.iter_mut().find(|slot| slot.as_ref().is_some_and(|recorded| recorded.matches(req)));
+        match next.and_then(Option::take) {
+            Some(interaction) => {
+                debug!("replaying {} {}", req.method, req.url);
+                interaction.response()
+            }
+            None => Err(Error::NotFound(
+                format!("Recorded response for {} {}", req.method, req.url),
+                Some("Record the session again with --record.".into()),
+            )),
+        }
+    }
+}
+
+
+/// Return the cassette files in a directory, sorted by name.
+fn cassette_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Url;
+    use std::{env, process};
+
+    use config::Config;
+    use http::Http;
+    use transport::{FakeTransport, REDACTED};
+
+
+    #[test]
+    fn record_then_replay() {
+        let dir = env::temp_dir().join(format!("ota-{}-cassettes", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let fake = FakeTransport::new();
+        let _ = fake
+            .respond(Response::new(StatusCode::OK, r#"{"access_token": "secret-token", "expires_in": 60}"#))
+            .respond(Response::new(StatusCode::OK, "plain text"));
+        let recorder = RecordingTransport::new(Arc::new(fake), &dir).expect("recorder");
+
+        let token = Request::post("http://auth/token")
+            .expect("request")
+            .basic_auth("id", "client-secret")
+            .form(&[("grant_type", "client_credentials"), ("client_secret", "client-secret")])
+            .expect("form");
+        let _ = recorder.execute(&token).expect("token");
+        let devices = Request::get("http://registry/api/v1/devices").expect("request").bearer_auth("secret-token");
+        let _ = recorder.execute(&devices).expect("devices");
+
+        for path in cassette_files(&dir).expect("cassettes") {
+            let contents = fs::read_to_string(&path).expect("cassette");
+            assert!(!contents.contains("secret-token") && !contents.contains("client-secret"), "{:?}", path);
+            assert!(contents.contains(REDACTED));
+        }
+
+        let url = Url::parse("http://registry").expect("url");
+        let mut config = Config::for_test(&url, "credentials.zip".into());
+        let trace = dir.join("replay.har");
+        config.trace_file = Some(trace.clone());
+        config.replay(&dir).expect("replay");
+        let resp = Http::send(Request::get("http://registry/api/v1/devices").expect("request"), &mut config).expect("replayed");
+        assert_eq!(resp.text(), "plain text");
+        match Http::send(Request::get("http://registry/api/v1/devices").expect("request"), &mut config) {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("unexpected replay: {:?}", other.map(|resp| resp.text())),
+        }
+        let har: Value = serde_json::from_slice(&fs::read(trace).expect("trace")).expect("har");
+        let statuses = har["log"]["entries"].as_array().expect("entries").iter().map(|entry| entry["response"]["status"].clone());
+        assert_eq!(statuses.collect::<Vec<_>>(), vec![json!(200), json!(0)]);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,5 +1,4 @@
 use clap::ArgMatches;
-use reqwest::Response;
 use std::str::FromStr;
 
 use api::{
@@ -8,13 +7,16 @@ use api::{
     registry::{DeviceType, GroupType, Registry, RegistryApi},
     reposerver::{Reposerver, ReposerverApi, TargetPackages, TufPackage, TufPackages},
 };
-use config::Config;
+use config::{Config, Profiles};
+use credentials::Inspection;
 use error::{Error, Result};
+use jobs;
+use output::Output;
 
 
-/// Execute a command then handle the HTTP `Response`.
+/// Execute a command then print the result.
 pub trait Exec<'a> {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()>;
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()>;
 }
 
 
@@ -22,6 +24,10 @@ pub trait Exec<'a> {
 #[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
 pub enum Command {
     Init,
+    Profile,
+    Config,
+    Auth,
+    Credentials,
     Campaign,
     Device,
     Group,
@@ -30,7 +36,7 @@ pub enum Command {
 }
 
 impl<'a> Exec<'a> for Command {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()> {
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
         if let Command::Init = self {
             Config::init_from_args(args)
         } else {
@@ -38,12 +44,16 @@ impl<'a> Exec<'a> for Command {
             let args = args.expect("sub-command args");
             #[cfg_attr(rustfmt, rustfmt_skip)]
             match self {
-                Command::Campaign => cmd.parse::<Campaign>()?.exec(args, reply),
-                Command::Device   => cmd.parse::<Device>()?.exec(args, reply),
-                Command::Group    => cmd.parse::<Group>()?.exec(args, reply),
-                Command::Package  => cmd.parse::<Package>()?.exec(args, reply),
-                Command::Update   => cmd.parse::<Update>()?.exec(args, reply),
-                Command::Init     => unreachable!()
+                Command::Profile     => cmd.parse::<Profile>()?.exec(args, out),
+                Command::Config      => cmd.parse::<Settings>()?.exec(args, out),
+                Command::Auth        => cmd.parse::<Auth>()?.exec(args, out),
+                Command::Credentials => cmd.parse::<Archive>()?.exec(args, out),
+                Command::Campaign    => cmd.parse::<Campaign>()?.exec(args, out),
+                Command::Device      => cmd.parse::<Device>()?.exec(args, out),
+                Command::Group       => cmd.parse::<Group>()?.exec(args, out),
+                Command::Package     => cmd.parse::<Package>()?.exec(args, out),
+                Command::Update      => cmd.parse::<Update>()?.exec(args, out),
+                Command::Init        => unreachable!()
             }
         }
     }
@@ -55,18 +65,161 @@ impl FromStr for Command {
     fn from_str(s: &str) -> Result<Self> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match s.to_lowercase().as_ref() {
-            "init"     => Ok(Command::Init),
-            "campaign" => Ok(Command::Campaign),
-            "device"   => Ok(Command::Device),
-            "group"    => Ok(Command::Group),
-            "package"  => Ok(Command::Package),
-            "update"   => Ok(Command::Update),
+            "init"        => Ok(Command::Init),
+            "profile"     => Ok(Command::Profile),
+            "config"      => Ok(Command::Config),
+            "auth"        => Ok(Command::Auth),
+            "credentials" => Ok(Command::Credentials),
+            "campaign"    => Ok(Command::Campaign),
+            "device"      => Ok(Command::Device),
+            "group"       => Ok(Command::Group),
+            "package"     => Ok(Command::Package),
+            "update"      => Ok(Command::Update),
             _ => Err(Error::Command(format!("unknown command: {}", s))),
         }
     }
 }
 
 
+/// Available profile sub-commands.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Profile {
+    List,
+    Use,
+    Remove,
+}
+
+impl<'a> Exec<'a> for Profile {
+    fn exec(&self, args: &ArgMatches<'a>, _: &Output) -> Result<()> {
+        let name = || args.value_of("name").expect("<name>");
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match self {
+            Profile::List   => Profiles::list(),
+            Profile::Use    => Profiles::set_default(name()),
+            Profile::Remove => Profiles::delete(name()),
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "list"   => Ok(Profile::List),
+            "use"    => Ok(Profile::Use),
+            "remove" => Ok(Profile::Remove),
+            _ => Err(Error::Command(format!("unknown profile subcommand: {}", s))),
+        }
+    }
+}
+
+
+/// Available config sub-commands.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Settings {
+    Show,
+    Set,
+    Validate,
+}
+
+impl<'a> Exec<'a> for Settings {
+    fn exec(&self, args: &ArgMatches<'a>, _: &Output) -> Result<()> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match self {
+            Settings::Show     => Config::load_from_args(args)?.show(),
+            Settings::Set      => Config::set_from_args(args),
+            Settings::Validate => Config::load_from_args(args)?.validate(),
+        }
+    }
+}
+
+impl FromStr for Settings {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "show"     => Ok(Settings::Show),
+            "set"      => Ok(Settings::Set),
+            "validate" => Ok(Settings::Validate),
+            _ => Err(Error::Command(format!("unknown config subcommand: {}", s))),
+        }
+    }
+}
+
+
+/// Available auth sub-commands.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Auth {
+    Namespaces,
+}
+
+impl<'a> Exec<'a> for Auth {
+    fn exec(&self, args: &ArgMatches<'a>, _: &Output) -> Result<()> {
+        match self {
+            Auth::Namespaces => Config::load_from_args(args)?.print_namespaces(),
+        }
+    }
+}
+
+impl FromStr for Auth {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "namespaces" => Ok(Auth::Namespaces),
+            _ => Err(Error::Command(format!("unknown auth subcommand: {}", s))),
+        }
+    }
+}
+
+
+/// Available credentials sub-commands.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Archive {
+    Inspect,
+}
+
+impl<'a> Exec<'a> for Archive {
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        match self {
+            Archive::Inspect => {
+                let inspection = match args.value_of("zip") {
+                    Some(zip) => Inspection::inspect(zip)?,
+                    None => Inspection::inspect(Config::load_from_args(args)?.credentials_zip)?,
+                };
+                if args.is_present("json") || args.is_present("output") {
+                    out.print(&inspection)?;
+                } else {
+                    inspection.print();
+                }
+                if inspection.is_ok() {
+                    Ok(())
+                } else {
+                    Err(Error::Parse(format!("{} problem(s) found in {}", inspection.problems.len(), inspection.path.display())))
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Archive {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "inspect" => Ok(Archive::Inspect),
+            _ => Err(Error::Command(format!("unknown credentials subcommand: {}", s))),
+        }
+    }
+}
+
+
 /// Available campaign sub-commands.
 #[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
 pub enum Campaign {
@@ -77,17 +230,23 @@ pub enum Campaign {
 }
 
 impl<'a> Exec<'a> for Campaign {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()> {
-        let mut config = Config::load_default()?;
-        let campaign = || args.value_of("campaign").expect("--campaign").parse();
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        let mut config = Config::load_from_args(args)?;
+        let campaign = |config: &mut Config| Campaigner::campaign_id(config, args.value_of("campaign").expect("--campaign"));
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Campaign::List   => Campaigner::list_from_args(&mut config, args),
-            Campaign::Create => Campaigner::create_from_args(&mut config, args),
-            Campaign::Launch => Campaigner::launch_campaign(&mut config, campaign()?),
-            Campaign::Cancel => Campaigner::cancel_campaign(&mut config, campaign()?),
-        }.and_then(reply)
+            Campaign::List   => Campaigner::list_from_args(&mut config, args, out),
+            Campaign::Create => out.print(&Campaigner::create_from_args(&mut config, args)?),
+            Campaign::Launch => {
+                let campaign = campaign(&mut config)?;
+                Campaigner::launch_campaign(&mut config, campaign)
+            }
+            Campaign::Cancel => {
+                let campaign = campaign(&mut config)?;
+                Campaigner::cancel_campaign(&mut config, campaign)
+            }
+        }
     }
 }
 
@@ -116,18 +275,20 @@ pub enum Device {
 }
 
 impl<'a> Exec<'a> for Device {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()> {
-        let mut config = Config::load_default()?;
-        let device = || args.value_of("device").expect("--device").parse();
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        let mut config = Config::load_from_args(args)?;
         let name = || args.value_of("name").expect("--name");
         let id = || args.value_of("id").expect("--id");
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Device::List   => Registry::list_device_args(&mut config, args),
-            Device::Create => Registry::create_device(&mut config, name(), id(), DeviceType::from_args(args)?),
-            Device::Delete => Registry::delete_device(&mut config, device()?),
-        }.and_then(reply)
+            Device::List   => Registry::list_device_args(&mut config, args, out),
+            Device::Create => out.print(&Registry::create_device(&mut config, name(), id(), DeviceType::from_args(args)?)?),
+            Device::Delete => {
+                let device = Registry::device_id(&mut config, args.value_of("device").expect("--device"))?;
+                Registry::delete_device(&mut config, device)
+            }
+        }
     }
 }
 
@@ -157,20 +318,29 @@ pub enum Group {
 }
 
 impl<'a> Exec<'a> for Group {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()> {
-        let mut config = Config::load_default()?;
-        let group = || args.value_of("group").expect("--group").parse();
-        let device = || args.value_of("device").expect("--device").parse();
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        let mut config = Config::load_from_args(args)?;
+        let group = |config: &mut Config| Registry::group_id(config, args.value_of("group").expect("--group"));
+        let devices = |config: &mut Config| Registry::device_ids(config, &args.values_of("device").expect("--device").collect::<Vec<_>>());
         let name = || args.value_of("name").expect("--name");
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Group::List   => Registry::list_group_args(&mut config, args),
-            Group::Create => Registry::create_group(&mut config, name(), GroupType::Static),
-            Group::Add    => Registry::add_to_group(&mut config, group()?, device()?),
-            Group::Remove => Registry::remove_from_group(&mut config, group()?, device()?),
-            Group::Rename => Registry::rename_group(&mut config, group()?, name()),
-        }.and_then(reply)
+            Group::List   => Registry::list_group_args(&mut config, args, out),
+            Group::Create => out.print(&Registry::create_group(&mut config, name(), GroupType::Static)?),
+            Group::Add    => {
+                let (group, devices) = (group(&mut config)?, devices(&mut config)?);
+                Registry::add_devices(&mut config, group, devices, jobs::from_args(args)?)
+            }
+            Group::Remove => {
+                let (group, devices) = (group(&mut config)?, devices(&mut config)?);
+                Registry::remove_devices(&mut config, group, devices, jobs::from_args(args)?)
+            }
+            Group::Rename => {
+                let group = group(&mut config)?;
+                Registry::rename_group(&mut config, group, name())
+            }
+        }
     }
 }
 
@@ -201,19 +371,19 @@ pub enum Package {
 }
 
 impl<'a> Exec<'a> for Package {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()> {
-        let mut config = Config::load_default()?;
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        let mut config = Config::load_from_args(args)?;
         let name = || args.value_of("name").expect("--name");
         let version = || args.value_of("version").expect("--version");
         let packages = || args.value_of("packages").expect("--packages");
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Package::List   => panic!("API not yet supported"),
+            Package::List   => out.print(&Reposerver::list_packages(&mut config)?),
             Package::Add    => Reposerver::add_package(&mut config, TufPackage::from_args(args)?),
-            Package::Fetch  => Reposerver::get_package(&mut config, name(), version()),
-            Package::Upload => Reposerver::add_packages(&mut config, TufPackages::from(TargetPackages::from_file(packages())?)?),
-        }.and_then(reply)
+            Package::Fetch  => out.print_bytes(&Reposerver::get_package(&mut config, name(), version())?),
+            Package::Upload => Reposerver::add_packages(&mut config, TufPackages::from(TargetPackages::from_file(packages())?)?, jobs::from_args(args)?),
+        }
     }
 }
 
@@ -241,17 +411,19 @@ pub enum Update {
 }
 
 impl<'a> Exec<'a> for Update {
-    fn exec(&self, args: &ArgMatches<'a>, reply: impl FnOnce(Response) -> Result<()>) -> Result<()> {
-        let mut config = Config::load_default()?;
+    fn exec(&self, args: &ArgMatches<'a>, out: &Output) -> Result<()> {
+        let mut config = Config::load_from_args(args)?;
         let update = || args.value_of("update").expect("--update").parse();
-        let device = || args.value_of("device").expect("--device").parse();
         let targets = || args.value_of("targets").expect("--targets");
 
         match self {
-            Update::Create => Director::create_mtu(&mut config, &TufUpdates::from(TargetRequests::from_file(targets())?)?),
-            Update::Launch => Director::launch_mtu(&mut config, update()?, device()?),
+            Update::Create => out.print(&Director::create_mtu(&mut config, &TufUpdates::from(TargetRequests::from_file(targets())?)?)?),
+            Update::Launch => {
+                let update = update()?;
+                let device = Registry::device_id(&mut config, args.value_of("device").expect("--device"))?;
+                Director::launch_mtu(&mut config, update, device)
+            }
         }
-        .and_then(reply)
     }
 }
 
@@ -1,30 +1,88 @@
 use clap::ArgMatches;
 use dirs;
-use serde_json;
+use serde_json::{self, Map, Value};
 use std::{
+    collections::BTreeMap,
+    env,
     fs::{self, File, OpenOptions},
-    io::{BufReader, ErrorKind, Read, Write},
+    io::{BufReader, ErrorKind, Read, Seek, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{Arc, Mutex},
 };
 use url::Url;
 use url_serde;
-use zip::ZipArchive;
+use zip::{result::ZipError, ZipArchive};
+
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 
 use api::auth_plus::{AccessToken, AuthPlus, AuthPlusApi, Credentials};
+use cassette::ReplayTransport;
 use error::{Error, Result};
+use http::{Http, HttpMethods, HttpSettings, RetryPolicy};
+use rate_limit::RateLimit;
+use token_store::TokenStore;
+use trace::TraceTransport;
+use transport::{DryRun, Transport};
 
 
 const CONFIG_FILE: &str = ".ota.conf";
+const CONFIG_VERSION: u64 = 2;
+const DEFAULT_PROFILE: &str = "default";
+
+/// Migrations to upgrade the config file layout, indexed by the version upgraded from.
+const MIGRATIONS: &[fn(Value) -> Result<Value>] = &[migrate_v1];
+
+/// Config fields with their overriding CLI flag and environment variable,
+/// and whether the field is required.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const OVERRIDES: &[(&str, &str, &str, bool)] = &[
+    ("credentials_zip", "credentials_zip", "OTA_CREDENTIALS",    true),
+    ("token",           "token",           "OTA_TOKEN",          false),
+    ("namespace",       "namespace",       "OTA_NAMESPACE",      false),
+    ("campaigner",      "campaigner_url",  "OTA_CAMPAIGNER_URL", true),
+    ("director",        "director_url",    "OTA_DIRECTOR_URL",   true),
+    ("registry",        "registry_url",    "OTA_REGISTRY_URL",   true),
+    ("reposerver",      "reposerver_url",  "OTA_REPOSERVER_URL", true),
+];
 
 /// Config values passed to API methods for making HTTP requests.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
+    #[serde(skip)]
+    pub profile: String,
+    /// Set when flags or environment variables changed the saved profile.
+    #[serde(skip)]
+    pub ephemeral: bool,
+    /// Set when the access token was passed in and must not be refreshed.
+    #[serde(skip)]
+    pub static_token: bool,
+    /// The transport shared by every request in this invocation.
+    #[serde(skip)]
+    pub transport: Option<Arc<dyn Transport>>,
+    /// A directory to record each request and response in.
+    #[serde(skip)]
+    pub record: Option<PathBuf>,
+    /// A HAR file to write each request and response to.
+    #[serde(skip)]
+    pub trace_file: Option<PathBuf>,
+    /// Print requests instead of sending them.
+    #[serde(skip)]
+    pub dry_run: Option<DryRun>,
+
     pub credentials_zip: PathBuf,
     #[serde(skip)]
     pub credentials: Option<Credentials>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<AccessToken>,
+    /// The access token shared by concurrent jobs, so it is refreshed and saved once.
+    #[serde(skip)]
+    pub shared_token: Option<Arc<Mutex<Option<AccessToken>>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_store: Option<TokenStore>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
 
     #[serde(with = "url_serde")]
     pub campaigner: Url,
@@ -34,53 +92,365 @@ pub struct Config {
     pub registry: Url,
     #[serde(with = "url_serde")]
     pub reposerver: Url,
+
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub http: HttpSettings,
+    /// Service names mapped to the rate limit for their requests.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rate_limits: BTreeMap<String, RateLimit>,
 }
 
 impl<'a> Config {
     /// Initialize a new config from CLI arguments.
     pub fn init_from_args(args: &ArgMatches<'a>) -> Result<()> {
+        let profile = arg_or_env(args, "profile", "OTA_PROFILE");
+        let profile = profile.as_deref();
         let credentials: PathBuf = args.value_of("credentials").expect("--credentials").into();
-        let campaigner = args.value_of("campaigner").expect("--campaigner").parse()?;
-        let director = args.value_of("director").expect("--director").parse()?;
-        let registry = args.value_of("registry").expect("--registry").parse()?;
-        Self::init(credentials, campaigner, director, registry)
+        let endpoints = Endpoints {
+            campaigner: parse_url_arg(args, "campaigner")?,
+            director:   parse_url_arg(args, "director")?,
+            registry:   parse_url_arg(args, "registry")?,
+            reposerver: parse_url_arg(args, "reposerver")?,
+        };
+        Self::init(profile, credentials, endpoints)
     }
 
-    /// Initialize a new config file.
-    pub fn init(credentials_zip: PathBuf, campaigner: Url, director: Url, registry: Url) -> Result<()> {
-        let reposerver = Self::reposerver_url(&credentials_zip)?;
-        Config {
+    /// Initialize a new config profile, or the default profile when `None`.
+    ///
+    /// Any endpoints not set are inferred from the contents of `credentials.zip`.
+    pub fn init(profile: Option<&str>, credentials_zip: PathBuf, endpoints: Endpoints) -> Result<()> {
+        let mut profiles = Profiles::load_or_empty()?;
+        let profile = profile.unwrap_or(&profiles.default).to_string();
+        debug!("initializing config profile {}", profile);
+
+        let inferred = Endpoints::from_zip(&credentials_zip)?;
+        let endpoint = |name: &str, url: Option<Url>, inferred: Option<Url>| {
+            url.or(inferred).ok_or_else(|| {
+                Error::NotFound(
+                    format!("{} URL", name),
+                    Some(format!("It could not be inferred from credentials.zip so please set --{}.", name.to_lowercase())),
+                )
+            })
+        };
+        let campaigner = endpoint("Campaigner", endpoints.campaigner, inferred.campaigner)?;
+        let director = endpoint("Director", endpoints.director, inferred.director)?;
+        let registry = endpoint("Registry", endpoints.registry, inferred.registry)?;
+        let reposerver = endpoint("Reposerver", endpoints.reposerver, inferred.reposerver)?;
+
+        let config = Config {
+            profile: profile.clone(),
+            ephemeral: false,
+            static_token: false,
+            transport: None,
+            record: None,
+            trace_file: None,
+            dry_run: None,
             credentials_zip,
             credentials: None,
             token: None,
+            shared_token: None,
+            token_store: None,
+            namespace: None,
             campaigner,
             director,
             registry,
             reposerver,
+            retry: RetryPolicy::default(),
+            http: HttpSettings::default(),
+            rate_limits: BTreeMap::new(),
+        };
+        if profiles.profiles.is_empty() {
+            profiles.default = profile.clone();
         }
-        .save_default()
+        let _ = profiles.profiles.insert(profile, config);
+        profiles.save_default()
     }
 
-    /// Save the default config file.
-    pub fn save_default(&self) -> Result<()> { self.save(Self::default_path()) }
+    /// Load the config profile then apply any overrides from CLI flags or
+    /// environment variables (in that order of precedence).
+    pub fn load_from_args(args: &ArgMatches<'a>) -> Result<Self> {
+        let profile = arg_or_env(args, "profile", "OTA_PROFILE");
+        let overrides = OVERRIDES
+            .iter()
+            .filter_map(|&(field, flag, env, _)| arg_or_env(args, flag, env).map(|val| (field, val)))
+            .collect::<Vec<_>>();
+        let mut config = Self::load_with_overrides(profile.as_deref(), &overrides)?;
+        config.record = args.value_of("record").map(PathBuf::from);
+        config.trace_file = args.value_of("trace_file").map(PathBuf::from);
+        if let Some(dir) = args.value_of("replay") {
+            config.replay(dir)?;
+        }
+        if args.is_present("dry_run") {
+            config.dry_run = Some(DryRun {
+                curl:       args.is_present("curl"),
+                allow_gets: args.is_present("allow_gets"),
+            });
+        }
+        Ok(config)
+    }
 
-    /// Load the default config file.
-    pub fn load_default() -> Result<Self> { Self::load(Self::default_path()) }
+    /// Serve every request from the cassettes recorded in `dir`.
+    ///
+    /// Nothing is sent over the network and no access token is needed. With
+    /// `trace_file` set, the replayed exchanges are traced too.
+    pub fn replay(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        let mut transport: Arc<dyn Transport> = Arc::new(ReplayTransport::open(dir)?);
+        if let Some(ref path) = self.trace_file {
+            debug!("tracing replayed requests to {:?}", path);
+            transport = Arc::new(TraceTransport::new(transport, path.clone()));
+        }
+        self.transport = Some(transport);
+        self.ephemeral = true;
+        self.static_token = true;
+        self.token = None;
+        Ok(())
+    }
 
-    /// Save the current config.
-    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
-        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
-        Ok(file.write_all(&serde_json::to_vec_pretty(&self)?)?)
+    /// Load the default config profile.
+    pub fn load_default() -> Result<Self> { Self::load_profile(None) }
+
+    /// Load a named config profile, or the default profile when `None`.
+    pub fn load_profile(profile: Option<&str>) -> Result<Self> {
+        let mut profiles = Profiles::load_default()?;
+        let profile = profile.unwrap_or(&profiles.default).to_string();
+        profiles.remove(&profile)
     }
 
-    /// Load a previously saved config.
-    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
-        fs::read(path)
-            .or_else(|err| match err.kind() {
-                ErrorKind::NotFound => Err(Error::NotFound("Config file".into(), Some("Please run `ota init` first.".into()))),
-                _ => Err(err.into()),
-            })
-            .and_then(|file| Ok(serde_json::from_slice(&file)?))
+    /// Load a config profile with field values replaced by `overrides`.
+    ///
+    /// No config file is required when the overrides set every field.
+    pub fn load_with_overrides(profile: Option<&str>, overrides: &[(&str, String)]) -> Result<Self> {
+        if overrides.is_empty() {
+            return Self::load_profile(profile);
+        }
+
+        let (name, fields) = match Profiles::load_default() {
+            Ok(mut profiles) => {
+                let name = profile.unwrap_or(&profiles.default).to_string();
+                match serde_json::to_value(profiles.remove(&name)?)? {
+                    Value::Object(fields) => (name, fields),
+                    _ => unreachable!(),
+                }
+            }
+            Err(Error::NotFound(..)) => {
+                debug!("no config file found, using overrides only");
+                (profile.unwrap_or(DEFAULT_PROFILE).to_string(), Map::new())
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut config = Self::apply_overrides(fields, overrides)?;
+        config.profile = name;
+        Ok(config)
+    }
+
+    /// Replace the config `fields` with each override value.
+    fn apply_overrides(mut fields: Map<String, Value>, overrides: &[(&str, String)]) -> Result<Self> {
+        for &(field, ref val) in overrides {
+            debug!("overriding config value {}", field);
+            let val = match field {
+                "token" => json!({"access_token": val, "token_type": "bearer", "expires_in": 0, "scope": ""}),
+                _ => Value::String(val.clone()),
+            };
+            let _ = fields.insert(field.into(), val);
+        }
+
+        for &(field, flag, env, required) in OVERRIDES {
+            if required && !fields.contains_key(field) {
+                Err(Error::NotFound(
+                    format!("Config value `{}`", field),
+                    Some(format!("Please set --{} or {}, or run `ota init`.", flag.replace('_', "-"), env)),
+                ))?
+            }
+        }
+
+        let mut config: Config = serde_json::from_value(Value::Object(fields))?;
+        config.ephemeral = true;
+        config.static_token = overrides.iter().any(|&(field, _)| field == "token");
+        Ok(config)
+    }
+
+    /// Save the current config to its profile.
+    pub fn save_profile(&self) -> Result<()> {
+        if self.ephemeral {
+            debug!("skipping save of overridden config profile {}", self.profile);
+            return Ok(());
+        }
+        let mut config = self.clone();
+        if let Some(ref store) = self.token_store {
+            if let Some(ref token) = self.token {
+                store.seal(token)?;
+            }
+            config.token = None;
+        }
+
+        let mut profiles = Profiles::load_or_empty()?;
+        let _ = profiles.profiles.insert(self.profile.clone(), config);
+        profiles.save_default()
+    }
+
+    /// Print the config with the access token redacted.
+    pub fn show(&self) -> Result<()> {
+        let mut fields = serde_json::to_value(self)?;
+        if let Some(token) = fields.get_mut("token") {
+            token["access_token"] = json!("<redacted>");
+        }
+        fields["profile"] = json!(self.profile);
+        println!("{}", serde_json::to_string_pretty(&fields)?);
+        Ok(())
+    }
+
+    /// Set a config value in the selected profile from CLI arguments.
+    pub fn set_from_args(args: &ArgMatches<'a>) -> Result<()> {
+        let profile = arg_or_env(args, "profile", "OTA_PROFILE");
+        let mut config = Self::load_profile(profile.as_deref())?;
+        config.set(args.value_of("key").expect("<key>"), args.value_of("value").expect("<value>"))?;
+        config.save_profile()
+    }
+
+    /// Set a config value by its (dot separated) key.
+    ///
+    /// The value is parsed as JSON where possible, or else used as a string.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if key.split('.').next() == Some("token") {
+            Err(Error::Config("the access token can't be set directly".into()))?
+        }
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+        let pointer = format!("/{}", key.replace('.', "/"));
+
+        let mut fields = serde_json::to_value(&*self)?;
+        {
+            let mut field = &mut fields;
+            for part in key.split('.') {
+                if field.is_null() {
+                    *field = Value::Object(Map::new());
+                }
+                field = match field {
+                    Value::Object(map) => map.entry(part).or_insert(Value::Null),
+                    _ => Err(Error::Config(format!("config key `{}` has no field `{}`", key, part)))?,
+                };
+            }
+            *field = value.clone();
+        }
+
+        let mut config: Config = serde_json::from_value(fields)?;
+        if !value.is_null() && serde_json::to_value(&config)?.pointer(&pointer).is_none() {
+            Err(Error::NotFound(format!("Config key `{}`", key), None))?
+        }
+        debug!("setting config value {} to {}", key, value);
+
+        config.profile = self.profile.clone();
+        if key == "credentials_zip" {
+            config.token = None;
+        } else {
+            config.credentials = self.credentials.take();
+        }
+        *self = config;
+        Ok(())
+    }
+
+    /// Check the credentials, access token and each service endpoint, printing the results.
+    pub fn validate(&mut self) -> Result<()> {
+        let mut failed = Vec::new();
+        let mut report = |name: &str, result: ::std::result::Result<String, String>| match result {
+            Ok(info) => println!("{:<12} ok     {}", name, info),
+            Err(err) => {
+                println!("{:<12} error  {}", name, err);
+                failed.push(name.to_string());
+            }
+        };
+
+        let credentials = self.credentials().map(|_| ());
+        report("credentials", match credentials {
+            Ok(()) => Ok(format!("{}", self.credentials_zip.display())),
+            Err(err) => Err(format!("{}", err)),
+        });
+        report("token", match self.token() {
+            Ok(Some(token)) => Ok(format!("scope: {}", token.scope)),
+            Ok(None) => Ok("no authentication required".into()),
+            Err(err) => Err(format!("{}", err)),
+        });
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let services = vec![
+            ("campaigner", format!("{}api/v2/campaigns?limit=1", self.campaigner)),
+            ("director",   format!("{}api/v1/admin/devices?limit=1", self.director)),
+            ("registry",   format!("{}api/v1/devices?limit=1", self.registry)),
+            ("reposerver", format!("{}api/v1/user_repo/root.json", self.reposerver)),
+        ];
+        for (name, url) in services {
+            report(name, match Http::get(&url, self) {
+                Ok(resp) => Ok(format!("{} {}", resp.status(), url)),
+                Err(err) => Err(format!("{}", err)),
+            });
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format!("validation failed for {}", failed.join(", "))))
+        }
+    }
+
+    /// Print the namespaces available to the access token, marking the selected one.
+    pub fn print_namespaces(&mut self) -> Result<()> {
+        let token = self.token()?.ok_or_else(|| Error::Auth("no access token for the current credentials".into()))?;
+        let selected = self.namespace(Some(&token)).unwrap_or(None);
+        for name in token.namespaces() {
+            let marker = if Some(name) == selected.as_deref() { "*" } else { " " };
+            println!("{} {}", marker, name);
+        }
+        Ok(())
+    }
+
+    /// Return the name of the service that the `url` belongs to.
+    pub fn service_for(&self, url: &Url) -> Option<&'static str> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let services = [
+            ("campaigner", &self.campaigner),
+            ("director",   &self.director),
+            ("registry",   &self.registry),
+            ("reposerver", &self.reposerver),
+        ];
+        services.iter().find(|(_, base)| url.as_str().starts_with(base.as_str())).map(|(name, _)| *name)
+    }
+
+    /// Return the base URL of the named service.
+    pub fn service_url(&mut self, service: &str) -> Result<Url> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match service {
+            "campaigner" => Ok(self.campaigner.clone()),
+            "director"   => Ok(self.director.clone()),
+            "registry"   => Ok(self.registry.clone()),
+            "reposerver" => Ok(self.reposerver.clone()),
+            "auth-plus"  => match self.credentials()?.auth_server() {
+                Some(server) => Ok(Url::parse(server)?),
+                None => Err(Error::Config("no auth-plus server in credentials.zip".into())),
+            },
+            _ => Err(Error::NotFound(format!("Service `{}`", service), None)),
+        }
+    }
+
+    /// Return the namespace to send with each request.
+    ///
+    /// A selected namespace must match one of the token's namespace scopes,
+    /// otherwise the token's only namespace scope is used.
+    pub fn namespace(&self, token: Option<&AccessToken>) -> Result<Option<String>> {
+        let scopes = token.map(AccessToken::namespaces).unwrap_or_default();
+        match self.namespace {
+            Some(ref name) if scopes.is_empty() || scopes.contains(&name.as_str()) => Ok(Some(name.clone())),
+            Some(ref name) => Err(Error::Token(format!("namespace `{}` not found in token scopes: {:?}", name, scopes))),
+            None => match scopes.len() {
+                0 => Ok(None),
+                1 => Ok(Some(scopes[0].into())),
+                _ => Err(Error::Token(format!(
+                    "multiple namespaces found: {:?}. Please select one with --namespace.",
+                    scopes
+                ))),
+            },
+        }
     }
 
     /// Parse `Credentials` or return an existing reference.
@@ -91,19 +461,255 @@ impl<'a> Config {
         Ok(self.credentials.as_ref().unwrap())
     }
 
-    /// Refresh an `AccessToken` or return existing.
+    /// Return a cached `AccessToken` or refresh when missing or expired.
     pub fn token(&mut self) -> Result<Option<AccessToken>> {
+        if self.static_token {
+            return Ok(self.token.clone());
+        }
+        match self.shared_token.clone() {
+            Some(shared) => {
+                let mut shared = shared.lock().expect("token lock");
+                self.token = shared.clone();
+                let token = self.cached_token();
+                *shared = self.token.clone();
+                token
+            }
+            None => self.cached_token(),
+        }
+    }
+
+    /// Return the cached token, or fetch one. With a shared token the caller
+    /// holds its lock, so this must not lock it again.
+    fn cached_token(&mut self) -> Result<Option<AccessToken>> {
+        if let (None, Some(store)) = (&self.token, &self.token_store) {
+            self.token = store.open().unwrap_or_else(|err| {
+                warn!("ignoring token store: {}", err);
+                None
+            });
+        }
         match self.token {
-            Some(_) => debug!("using cached access token..."),
+            Some(ref token) if !token.is_expired() => debug!("using cached access token..."),
+            Some(_) => {
+                debug!("cached access token expired...");
+                let _ = self.fetch_token()?;
+            }
             None => {
-                if let Some(token) = AuthPlus::refresh_token(self)? {
-                    self.token = Some(token);
-                    self.save_default()?;
+                let _ = self.fetch_token()?;
+            }
+        }
+        Ok(self.token.clone())
+    }
+
+    /// Fetch and save a new `AccessToken`, discarding any cached token.
+    ///
+    /// With a shared token, a token already refreshed by another job is used
+    /// instead of fetching another one.
+    pub fn refresh_token(&mut self) -> Result<Option<AccessToken>> {
+        if self.static_token {
+            Err(Error::Auth("access token from --token or OTA_TOKEN was rejected".into()))?
+        }
+        match self.shared_token.clone() {
+            Some(shared) => {
+                let mut shared = shared.lock().expect("token lock");
+                let rejected = self.token.as_ref().map(|token| &token.access_token);
+                if shared.is_some() && shared.as_ref().map(|token| &token.access_token) != rejected {
+                    debug!("using access token refreshed by another job...");
+                    self.token = shared.clone();
+                    return Ok(self.token.clone());
                 }
+                let token = self.fetch_token();
+                *shared = self.token.clone();
+                token
             }
+            None => self.fetch_token(),
+        }
+    }
+
+    fn fetch_token(&mut self) -> Result<Option<AccessToken>> {
+        self.token = None;
+        if let Some(token) = AuthPlus::refresh_token(self)? {
+            self.token = Some(token);
+            self.save_profile()?;
         }
         Ok(self.token.clone())
     }
+}
+
+
+/// Server endpoints to set in a new config.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Endpoints {
+    pub campaigner: Option<Url>,
+    pub director:   Option<Url>,
+    pub registry:   Option<Url>,
+    pub reposerver: Option<Url>,
+}
+
+impl Endpoints {
+    /// Host name prefixes of services that may be replaced to find sibling services.
+    const SERVICE_HOSTS: &'static [&'static str] = &["tuf-reposerver", "reposerver", "treehub", "ota-treehub"];
+
+    /// Infer the server endpoints from the contents of `credentials.zip`.
+    pub fn from_zip(credentials_zip: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(&credentials_zip)?;
+        let mut archive = ZipArchive::new(BufReader::new(file))?;
+        let gateway = read_zip_url(&mut archive, "api_gateway.url")?;
+        let reposerver = read_zip_url(&mut archive, "tufrepo.url")?;
+        let autoprov = read_zip_url(&mut archive, "autoprov.url")?;
+        let treehub = match Credentials::parse(&credentials_zip) {
+            Ok(credentials) => Some(credentials.ostree_server().clone()),
+            Err(err) => {
+                debug!("skipping treehub.json: {}", err);
+                None
+            }
+        };
+
+        let siblings = vec![reposerver.clone(), treehub, autoprov].into_iter().flatten().collect::<Vec<_>>();
+        Ok(Self::infer(gateway, reposerver, &siblings))
+    }
+
+    /// Use the API gateway for all services, or else a sibling host of another service.
+    fn infer(gateway: Option<Url>, reposerver: Option<Url>, siblings: &[Url]) -> Self {
+        let service = |name: &str| match gateway {
+            Some(ref gateway) => Some(with_trailing_slash(gateway.clone())),
+            None => siblings.iter().filter_map(|url| Self::sibling(url, name)).next(),
+        };
+        Endpoints {
+            campaigner: service("campaigner"),
+            director:   service("director"),
+            registry:   service("device-registry"),
+            reposerver: reposerver.map(with_trailing_slash),
+        }
+    }
+
+    /// Replace a known service name in the host, e.g. `tuf-reposerver.example.com` to `director.example.com`.
+    fn sibling(url: &Url, service: &str) -> Option<Url> {
+        let domain = url.domain()?;
+        let (label, rest) = domain.split_at(domain.find('.')?);
+        if !Self::SERVICE_HOSTS.contains(&label) {
+            return None;
+        }
+
+        let mut url = url.clone();
+        url.set_host(Some(&format!("{}{}", service, rest))).ok()?;
+        url.set_path("/");
+        url.set_query(None);
+        debug!("inferred {} url {}", service, url);
+        Some(url)
+    }
+}
+
+
+/// Named config profiles and the default profile to use.
+#[derive(Serialize, Deserialize)]
+pub struct Profiles {
+    pub version:  u64,
+    pub default:  String,
+    pub profiles: BTreeMap<String, Config>,
+}
+
+impl Profiles {
+    /// Save the default config file.
+    pub fn save_default(&self) -> Result<()> { self.save(Self::default_path()) }
+
+    /// Load the default config file.
+    pub fn load_default() -> Result<Self> { Self::load(Self::default_path()) }
+
+    /// Load the default config file or start without any profiles.
+    pub fn load_or_empty() -> Result<Self> {
+        Self::load_default().or_else(|err| match err {
+            Error::NotFound(..) => Ok(Profiles {
+                version:  CONFIG_VERSION,
+                default:  DEFAULT_PROFILE.into(),
+                profiles: BTreeMap::new(),
+            }),
+            _ => Err(err),
+        })
+    }
+
+    /// Save all profiles with the current config version.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut fields = serde_json::to_value(self)?;
+        fields["version"] = json!(CONFIG_VERSION);
+        write_private_file(path, &serde_json::to_vec_pretty(&fields)?)
+    }
+
+    /// Load previously saved profiles.
+    ///
+    /// A config file written by an older version is migrated to the current
+    /// layout, keeping a backup of the original file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs::read(path).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => Error::NotFound("Config file".into(), Some("Please run `ota init` first.".into())),
+            _ => err.into(),
+        })?;
+        warn_if_readable(path)?;
+
+        let (fields, version) = migrate(serde_json::from_slice(&file)?)?;
+        let mut profiles: Profiles = serde_json::from_value(fields)
+            .map_err(|err| Error::Config(format!("parsing config file {}: {}", path.display(), err)))?;
+
+        if version < CONFIG_VERSION {
+            let backup = path.with_file_name(format!("{}.v{}.bak", CONFIG_FILE, version));
+            info!("migrated config file from version {} (backup saved to {})", version, backup.display());
+            write_private_file(&backup, &file)?;
+            profiles.save(path)?;
+        }
+
+        for (name, config) in &mut profiles.profiles {
+            config.profile = name.clone();
+        }
+        Ok(profiles)
+    }
+
+    /// Remove and return a named profile.
+    pub fn remove(&mut self, profile: &str) -> Result<Config> {
+        self.profiles.remove(profile).ok_or_else(|| Self::not_found(profile))
+    }
+
+    /// Print the available profiles, marking the default.
+    pub fn list() -> Result<()> {
+        let profiles = Self::load_default()?;
+        for name in profiles.profiles.keys() {
+            let marker = if *name == profiles.default { "*" } else { " " };
+            println!("{} {}", marker, name);
+        }
+        Ok(())
+    }
+
+    /// Set the default profile to use.
+    pub fn set_default(profile: &str) -> Result<()> {
+        let mut profiles = Self::load_default()?;
+        if !profiles.profiles.contains_key(profile) {
+            return Err(Self::not_found(profile));
+        }
+        debug!("setting default profile to {}", profile);
+        profiles.default = profile.into();
+        profiles.save_default()
+    }
+
+    /// Delete a profile, choosing a new default when required.
+    pub fn delete(profile: &str) -> Result<()> {
+        let mut profiles = Self::load_default()?;
+        let _ = profiles.remove(profile)?;
+        debug!("removed profile {}", profile);
+        if profiles.default == profile {
+            profiles.default = match profiles.profiles.keys().next() {
+                Some(name) => name.clone(),
+                None => DEFAULT_PROFILE.into(),
+            };
+        }
+        profiles.save_default()
+    }
+
+    /// Return an error for an unknown profile name.
+    fn not_found(profile: &str) -> Error {
+        Error::NotFound(
+            format!("Profile `{}`", profile),
+            Some("Please run `ota init --profile <name>` or `ota profile list`.".into()),
+        )
+    }
 
     /// Return the default config path.
     fn default_path() -> PathBuf {
@@ -112,15 +718,310 @@ impl<'a> Config {
         path.push(CONFIG_FILE);
         path
     }
+}
+
+
+/// Upgrade the config file fields to the current version, returning the original version.
+///
+/// Files without a `version` field are either version 1 (a single config) or
+/// version 2 (named profiles).
+fn migrate(mut fields: Value) -> Result<(Value, u64)> {
+    let version = match fields.get("version") {
+        Some(version) => version.as_u64().ok_or_else(|| Error::Config(format!("invalid config version: {}", version)))?,
+        None if fields.get("profiles").is_some() => 2,
+        None => 1,
+    };
+    if version == 0 || version > CONFIG_VERSION {
+        Err(Error::Config(format!(
+            "config file version {} is not supported (expected version {} or older), please upgrade ota",
+            version, CONFIG_VERSION
+        )))?
+    }
 
-    /// Parse credentials.zip and return the TUF Reposerver URL.
-    fn reposerver_url(credentials_zip: impl AsRef<Path>) -> Result<Url> {
-        debug!("reading tufrepo.url from credentials.zip");
-        let file = File::open(credentials_zip)?;
-        let mut archive = ZipArchive::new(BufReader::new(file))?;
-        let mut tufrepo = archive.by_name("tufrepo.url")?;
-        let mut contents = String::new();
-        let _ = tufrepo.read_to_string(&mut contents)?;
-        Ok(Url::from_str(&contents)?)
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        debug!("migrating config file from version {} to {}", from + 1, from + 2);
+        fields = migration(fields)?;
+    }
+    fields["version"] = json!(CONFIG_VERSION);
+    Ok((fields, version))
+}
+
+/// Move a single config into the default profile.
+fn migrate_v1(fields: Value) -> Result<Value> {
+    Ok(json!({
+        "default": DEFAULT_PROFILE,
+        "profiles": { DEFAULT_PROFILE: fields },
+    }))
+}
+
+/// Parse an optional CLI flag value as a URL.
+fn parse_url_arg<'a>(args: &ArgMatches<'a>, flag: &str) -> Result<Option<Url>> {
+    match args.value_of(flag) {
+        Some(url) => Ok(Some(url.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Read an optional file containing a URL from the zip archive.
+fn read_zip_url<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<Url>> {
+    match read_zip_file(archive, name)? {
+        Some(contents) => Ok(Some(Url::from_str(String::from_utf8_lossy(&contents).trim())?)),
+        None => Ok(None),
+    }
+}
+
+/// Read the contents of an optional file from the zip archive.
+pub(crate) fn read_zip_file<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<Vec<u8>>> {
+    let mut contents = Vec::new();
+    match archive.by_name(name) {
+        Ok(mut file) => {
+            debug!("reading {} from credentials.zip", name);
+            let _ = file.read_to_end(&mut contents)?;
+        }
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => Err(err)?,
+    }
+    Ok(Some(contents))
+}
+
+/// Append a trailing slash so the URL can be used as a base path.
+fn with_trailing_slash(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
+
+/// Create or replace a file that only the current user can read or write.
+///
+/// An existing file is restricted before the contents are written.
+pub(crate) fn write_private_file(path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    let _ = options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    let _ = options.mode(0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file.write_all(contents)?)
+}
+
+/// Log a warning when a file can be read by other users.
+#[cfg(unix)]
+fn warn_if_readable(path: impl AsRef<Path>) -> Result<()> {
+    let mode = fs::metadata(&path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        warn!(
+            "{:?} is accessible by other users (mode {:o}), please run `chmod 600 {}`",
+            path.as_ref(),
+            mode & 0o777,
+            path.as_ref().display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable(_: impl AsRef<Path>) -> Result<()> { Ok(()) }
+
+/// Return the CLI flag value, or else a non-empty environment variable.
+fn arg_or_env<'a>(args: &ArgMatches<'a>, flag: &str, env: &str) -> Option<String> {
+    args.value_of(flag)
+        .map(String::from)
+        .or_else(|| env::var(env).ok().filter(|val| !val.is_empty()))
+}
+
+
+#[cfg(test)]
+impl Config {
+    /// An ephemeral config with every service at `url`.
+    pub(crate) fn for_test(url: &Url, credentials_zip: PathBuf) -> Self {
+        Config {
+            profile: "test".into(),
+            ephemeral: true,
+            static_token: false,
+            transport: None,
+            record: None,
+            trace_file: None,
+            dry_run: None,
+            credentials_zip,
+            credentials: None,
+            token: None,
+            shared_token: None,
+            token_store: None,
+            namespace: None,
+            campaigner: url.clone(),
+            director: url.clone(),
+            registry: url.clone(),
+            reposerver: url.clone(),
+            retry: RetryPolicy::default(),
+            http: HttpSettings::default(),
+            rate_limits: BTreeMap::new(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn overrides() -> Vec<(&'static str, String)> {
+        vec![
+            ("credentials_zip", "/tmp/credentials.zip".into()),
+            ("token", "some-token".into()),
+            ("campaigner", "http://campaigner/".into()),
+            ("director", "http://director/".into()),
+            ("registry", "http://registry/".into()),
+            ("reposerver", "http://reposerver/".into()),
+        ]
+    }
+
+    #[test]
+    fn overrides_without_config_file() {
+        let config = Config::apply_overrides(Map::new(), &overrides()).expect("config");
+        assert!(config.ephemeral);
+        assert!(config.static_token);
+        assert_eq!(config.credentials_zip, PathBuf::from("/tmp/credentials.zip"));
+        assert_eq!(config.token.expect("token").access_token, "some-token");
+        assert_eq!(config.director.as_str(), "http://director/");
+    }
+
+    #[test]
+    fn overrides_replace_profile_values() {
+        let fields = match json!({
+            "credentials_zip": "/home/credentials.zip",
+            "campaigner": "http://old-campaigner/",
+            "director": "http://old-director/",
+            "registry": "http://old-registry/",
+            "reposerver": "http://old-reposerver/",
+        }) {
+            Value::Object(fields) => fields,
+            _ => unreachable!(),
+        };
+        let config = Config::apply_overrides(fields, &[("director", "http://director/".into())]).expect("config");
+        assert!(!config.static_token);
+        assert_eq!(config.credentials_zip, PathBuf::from("/home/credentials.zip"));
+        assert_eq!(config.campaigner.as_str(), "http://old-campaigner/");
+        assert_eq!(config.director.as_str(), "http://director/");
+    }
+
+    #[test]
+    fn infer_endpoints_from_gateway() {
+        let gateway = Url::parse("https://gateway.example.com").expect("url");
+        let reposerver = Url::parse("https://repo.example.com/api/v1").expect("url");
+        let endpoints = Endpoints::infer(Some(gateway.clone()), Some(reposerver), &[]);
+        assert_eq!(endpoints.campaigner, Some(gateway.clone()));
+        assert_eq!(endpoints.director, Some(gateway.clone()));
+        assert_eq!(endpoints.registry, Some(gateway));
+        assert_eq!(endpoints.reposerver.expect("reposerver").as_str(), "https://repo.example.com/api/v1/");
+    }
+
+    #[test]
+    fn infer_endpoints_from_sibling_hosts() {
+        let reposerver = Url::parse("http://tuf-reposerver.gw.staging.example.com/").expect("url");
+        let endpoints = Endpoints::infer(None, Some(reposerver.clone()), &[reposerver]);
+        let url = |url: Option<Url>| url.expect("url").to_string();
+        assert_eq!(url(endpoints.campaigner), "http://campaigner.gw.staging.example.com/");
+        assert_eq!(url(endpoints.director), "http://director.gw.staging.example.com/");
+        assert_eq!(url(endpoints.registry), "http://device-registry.gw.staging.example.com/");
+
+        let unknown = Url::parse("https://example.com/repo/").expect("url");
+        assert_eq!(Endpoints::infer(None, None, &[unknown]), Endpoints::default());
+    }
+
+    #[test]
+    fn select_token_namespace() {
+        let mut config = Config::apply_overrides(Map::new(), &overrides()).expect("config");
+        let mut token = config.token.clone().expect("token");
+        token.scope = "namespace.one namespace.two".into();
+        assert!(config.namespace(Some(&token)).is_err());
+
+        config.namespace = Some("two".into());
+        assert_eq!(config.namespace(Some(&token)).expect("namespace"), Some("two".into()));
+        config.namespace = Some("three".into());
+        assert!(config.namespace(Some(&token)).is_err());
+        assert_eq!(config.namespace(None).expect("namespace"), Some("three".into()));
+
+        config.namespace = None;
+        token.scope = "namespace.one".into();
+        assert_eq!(config.namespace(Some(&token)).expect("namespace"), Some("one".into()));
+    }
+
+    #[test]
+    fn use_token_refreshed_by_another_job() {
+        let mut config = Config::apply_overrides(Map::new(), &overrides()).expect("config");
+        config.static_token = false;
+        let mut fresh = config.token.clone().expect("token");
+        fresh.access_token = "fresh-token".into();
+        config.shared_token = Some(Arc::new(Mutex::new(Some(fresh))));
+        let token = config.refresh_token().expect("refresh").expect("token");
+        assert_eq!(token.access_token, "fresh-token");
+        assert_eq!(config.token.expect("token").access_token, "fresh-token");
+    }
+
+    #[test]
+    fn set_config_values() {
+        let mut config = Config::apply_overrides(Map::new(), &overrides()).expect("config");
+        config.set("director", "http://new-director/").expect("set director");
+        assert_eq!(config.director.as_str(), "http://new-director/");
+        assert!(config.set("director", "not a url").is_err());
+        assert!(config.set("token", "another-token").is_err());
+        match config.set("unknown", "value") {
+            Err(Error::NotFound(name, _)) => assert_eq!(name, "Config key `unknown`"),
+            _ => panic!("expected unknown key"),
+        }
+    }
+
+    #[test]
+    fn migrate_config_versions() {
+        let config = json!({
+            "credentials_zip": "/home/credentials.zip",
+            "campaigner": "http://campaigner/",
+            "director": "http://director/",
+            "registry": "http://registry/",
+            "reposerver": "http://reposerver/",
+        });
+        let (fields, version) = migrate(config.clone()).expect("migrate v1");
+        assert_eq!(version, 1);
+        assert_eq!(fields["version"], json!(CONFIG_VERSION));
+        assert_eq!(fields["profiles"][DEFAULT_PROFILE], config);
+        let profiles: Profiles = serde_json::from_value(fields.clone()).expect("profiles");
+        assert_eq!(profiles.default, DEFAULT_PROFILE);
+
+        let (unchanged, version) = migrate(fields.clone()).expect("migrate v2");
+        assert_eq!(version, CONFIG_VERSION);
+        assert_eq!(unchanged, fields);
+
+        let mut newer = fields;
+        newer["version"] = json!(CONFIG_VERSION + 1);
+        match migrate(newer) {
+            Err(Error::Config(err)) => assert!(err.contains("please upgrade")),
+            _ => panic!("expected newer version error"),
+        }
+    }
+
+    #[test]
+    fn missing_override_values() {
+        let mut overrides = overrides();
+        overrides.retain(|&(field, _)| field != "registry");
+        match Config::apply_overrides(Map::new(), &overrides) {
+            Err(Error::NotFound(name, _)) => assert_eq!(name, "Config value `registry`"),
+            _ => panic!("expected missing registry value"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restrict_existing_private_files() {
+        let path = env::temp_dir().join(format!("ota-{}-private.json", ::std::process::id()));
+        fs::write(&path, "{}").expect("write");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).expect("chmod");
+        write_private_file(&path, b"secret").expect("write private");
+        assert_eq!(fs::metadata(&path).expect("metadata").permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read(&path).expect("read"), b"secret");
+        let _ = fs::remove_file(path);
     }
 }
@@ -0,0 +1,331 @@
+use openssl::{nid::Nid, pkcs12::Pkcs12, pkey::PKey, x509::X509};
+use serde_json::{self, Value};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::{Path, PathBuf},
+};
+use url::Url;
+use zip::ZipArchive;
+
+use api::auth_plus::{ClientTls, Credentials};
+use config::read_zip_file;
+use error::Result;
+use output::Render;
+use transport::REDACTED;
+
+
+const TREEHUB_JSON: &str = "treehub.json";
+const TUFREPO_URL: &str = "tufrepo.url";
+const URL_FILES: &[&str] = &["tufrepo.url", "api_gateway.url", "autoprov.url"];
+
+/// A summary of the contents of a `credentials.zip` file.
+#[derive(Serialize, Debug)]
+pub struct Inspection {
+    pub path:          PathBuf,
+    pub entries:       Vec<Entry>,
+    pub auth:          Option<String>,
+    pub treehub:       Option<Value>,
+    pub ostree_server: Option<String>,
+    pub tufrepo:       Option<String>,
+    pub urls:          Vec<Entry>,
+    pub material:      Vec<Entry>,
+    pub problems:      Vec<String>,
+}
+
+impl Render for Inspection {
+    fn columns() -> &'static [&'static str] { &["path", "auth", "ostree_server", "tufrepo", "problems"] }
+
+    fn id() -> &'static str { "path" }
+}
+
+/// An archive entry with a short description of its contents.
+#[derive(Serialize, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+}
+
+impl Inspection {
+    /// Read and check every entry in the `credentials.zip` file.
+    pub fn inspect(credentials_zip: impl AsRef<Path>) -> Result<Self> {
+        debug!("inspecting credentials zip: {:?}", credentials_zip.as_ref());
+        let file = File::open(&credentials_zip)?;
+        let mut archive = ZipArchive::new(BufReader::new(file))?;
+
+        let mut inspection = Inspection {
+            path:          credentials_zip.as_ref().to_path_buf(),
+            entries:       Vec::new(),
+            auth:          None,
+            treehub:       None,
+            ostree_server: None,
+            tufrepo:       None,
+            urls:          Vec::new(),
+            material:      Vec::new(),
+            problems:      Vec::new(),
+        };
+
+        for index in 0..archive.len() {
+            let file = archive.by_index(index)?;
+            inspection.entries.push(Entry {
+                name: file.name().into(),
+                size: file.size(),
+                info: None,
+            });
+        }
+
+        inspection.check_treehub(&mut archive)?;
+        inspection.check_urls(&mut archive)?;
+        inspection.check_material(&mut archive)?;
+        Ok(inspection)
+    }
+
+    /// Print the inspection in a human readable layout.
+    pub fn print(&self) {
+        println!("archive: {}", self.path.display());
+        for entry in &self.entries {
+            println!("  {:>8}  {}", entry.size, entry.name);
+        }
+
+        println!("auth: {}", self.auth.as_ref().map_or("unknown", String::as_str));
+        println!("ostree server: {}", self.ostree_server.as_ref().map_or("-", String::as_str));
+        println!("tufrepo.url: {}", self.tufrepo.as_ref().map_or("-", String::as_str));
+        for entry in self.urls.iter().filter(|entry| entry.name != TUFREPO_URL) {
+            println!("{}: {}", entry.name, entry.info.as_ref().map_or("-", String::as_str));
+        }
+        if let Some(ref treehub) = self.treehub {
+            println!("{}:", TREEHUB_JSON);
+            for line in serde_json::to_string_pretty(treehub).unwrap_or_default().lines() {
+                println!("  {}", line);
+            }
+        }
+        if !self.material.is_empty() {
+            println!("key material:");
+            for entry in &self.material {
+                println!("  {}: {}", entry.name, entry.info.as_ref().map_or("-", String::as_str));
+            }
+        }
+        if !self.problems.is_empty() {
+            println!("problems:");
+            for problem in &self.problems {
+                println!("  - {}", problem);
+            }
+        }
+    }
+
+    /// Whether any entry is missing or malformed.
+    pub fn is_ok(&self) -> bool { self.problems.is_empty() }
+
+    fn has_entry(&self, name: &str) -> bool { self.entries.iter().any(|entry| entry.name == name) }
+
+    /// Decode `treehub.json` with the client secret redacted.
+    fn check_treehub<R: Read + Seek>(&mut self, archive: &mut ZipArchive<R>) -> Result<()> {
+        let contents = match read_zip_file(archive, TREEHUB_JSON)? {
+            Some(contents) => contents,
+            None => {
+                self.problems.push(format!("missing {}", TREEHUB_JSON));
+                return Ok(());
+            }
+        };
+        let mut treehub = match serde_json::from_slice::<Value>(&contents) {
+            Ok(treehub) => treehub,
+            Err(err) => {
+                self.problems.push(format!("malformed {}: {}", TREEHUB_JSON, err));
+                return Ok(());
+            }
+        };
+
+        if let Err(err) = serde_json::from_value::<Credentials>(treehub.clone()) {
+            self.problems.push(format!("malformed {}: {}", TREEHUB_JSON, err));
+        }
+        self.ostree_server = treehub.pointer("/ostree/server").and_then(Value::as_str).map(String::from);
+        if self.ostree_server.is_none() {
+            self.problems.push(format!("missing ostree.server in {}", TREEHUB_JSON));
+        }
+
+        let no_auth = treehub.get("no_auth").and_then(Value::as_bool).unwrap_or(false);
+        let tls = match ClientTls::from_archive(archive) {
+            Ok(tls) => tls.is_some(),
+            Err(err) => {
+                self.problems.push(format!("client certificate: {}", err));
+                false
+            }
+        };
+        self.auth = if no_auth {
+            Some("none".into())
+        } else if treehub.get("oauth2").is_some() {
+            Some("oauth2".into())
+        } else if tls {
+            Some("tls".into())
+        } else {
+            self.problems.push("no oauth2 section, no_auth flag or client certificate found".into());
+            None
+        };
+
+        if let Some(secret) = treehub.pointer_mut("/oauth2/client_secret") {
+            *secret = Value::String(REDACTED.into());
+        }
+        self.treehub = Some(treehub);
+        Ok(())
+    }
+
+    /// Check the service URLs stored alongside `treehub.json`.
+    fn check_urls<R: Read + Seek>(&mut self, archive: &mut ZipArchive<R>) -> Result<()> {
+        if !self.has_entry(TUFREPO_URL) {
+            self.problems.push(format!("missing {}", TUFREPO_URL));
+        }
+        for name in URL_FILES {
+            let contents = match read_zip_file(archive, name)? {
+                Some(contents) => contents,
+                None => continue,
+            };
+            let text = String::from_utf8_lossy(&contents).trim().to_string();
+            match Url::parse(&text) {
+                Ok(_) if *name == TUFREPO_URL => self.tufrepo = Some(text.clone()),
+                Ok(_) => (),
+                Err(err) => self.problems.push(format!("malformed {}: {}", name, err)),
+            }
+            self.urls.push(Entry {
+                name: (*name).into(),
+                size: contents.len() as u64,
+                info: Some(text),
+            });
+        }
+        Ok(())
+    }
+
+    /// Describe any certificates, private keys and TUF keys.
+    fn check_material<R: Read + Seek>(&mut self, archive: &mut ZipArchive<R>) -> Result<()> {
+        let names = self.entries.iter().map(|entry| entry.name.clone()).collect::<Vec<_>>();
+        for name in names {
+            let kind = if name.ends_with(".pem") || name.ends_with(".crt") {
+                describe_pem
+            } else if name.ends_with(".p12") {
+                describe_pkcs12
+            } else if name.ends_with(".pub") || name.ends_with(".sec") {
+                describe_tuf_key
+            } else {
+                continue;
+            };
+
+            let contents = read_zip_file(archive, &name)?.unwrap_or_default();
+            let info = match kind(&contents) {
+                Ok(info) => info,
+                Err(err) => {
+                    self.problems.push(format!("malformed {}: {}", name, err));
+                    "malformed".into()
+                }
+            };
+            self.material.push(Entry {
+                name,
+                size: contents.len() as u64,
+                info: Some(info),
+            });
+        }
+        Ok(())
+    }
+}
+
+
+/// Describe a PEM certificate or private key without revealing the key.
+fn describe_pem(contents: &[u8]) -> ::std::result::Result<String, String> {
+    if let Ok(cert) = X509::from_pem(contents) {
+        let name = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok())
+            .unwrap_or_else(|| "-".into());
+        Ok(format!("certificate (CN={}, expires {})", name, cert.not_after()))
+    } else if let Ok(key) = PKey::private_key_from_pem(contents) {
+        Ok(format!("private key ({} bits)", key.bits()))
+    } else if let Ok(key) = PKey::public_key_from_pem(contents) {
+        Ok(format!("public key ({} bits)", key.bits()))
+    } else {
+        Err("not a PEM certificate or key".into())
+    }
+}
+
+/// Describe a PKCS#12 bundle, opening it when it has no password.
+fn describe_pkcs12(contents: &[u8]) -> ::std::result::Result<String, String> {
+    let pkcs12 = Pkcs12::from_der(contents).map_err(|err| format!("not a PKCS#12 bundle: {}", err))?;
+    match pkcs12.parse2("") {
+        Ok(parsed) => match parsed.cert {
+            Some(cert) => Ok(format!("PKCS#12 bundle ({} certificate)", describe_pem(&cert.to_pem().unwrap_or_default())?)),
+            None => Ok("PKCS#12 bundle (no certificate)".into()),
+        },
+        Err(_) => Ok("PKCS#12 bundle (password protected)".into()),
+    }
+}
+
+/// Describe a TUF key by its type only.
+fn describe_tuf_key(contents: &[u8]) -> ::std::result::Result<String, String> {
+    let key = serde_json::from_slice::<Value>(contents).map_err(|err| format!("not a TUF key: {}", err))?;
+    match key.get("keytype").and_then(Value::as_str) {
+        Some(keytype) => Ok(format!("TUF key ({})", keytype)),
+        None => Err("missing keytype".into()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, io::Write, process};
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+
+    fn write_zip(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let path = env::temp_dir().join(format!("ota-{}-{}", process::id(), name));
+        let mut zip = ZipWriter::new(File::create(&path).expect("create zip"));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, contents) in files {
+            zip.start_file(*name, options).expect("zip file");
+            zip.write_all(contents).expect("zip file");
+        }
+        let _ = zip.finish().expect("finish zip");
+        path
+    }
+
+    #[test]
+    fn inspect_oauth2_credentials() {
+        let treehub = br#"{
+            "oauth2": {"server": "https://auth", "client_id": "id", "client_secret": "very-secret"},
+            "ostree": {"server": "https://treehub/api/v3/"}
+        }"#;
+        let path = write_zip("inspect-oauth2.zip", &[
+            ("treehub.json", treehub),
+            ("tufrepo.url", b"https://reposerver/"),
+            ("targets.pub", br#"{"keytype": "ED25519", "keyval": {"public": "abc"}}"#),
+        ]);
+
+        let inspection = Inspection::inspect(&path).expect("inspect");
+        assert!(inspection.is_ok(), "{:?}", inspection.problems);
+        assert_eq!(inspection.auth, Some("oauth2".into()));
+        assert_eq!(inspection.ostree_server, Some("https://treehub/api/v3/".into()));
+        assert_eq!(inspection.tufrepo, Some("https://reposerver/".into()));
+        assert_eq!(inspection.material[0].info, Some("TUF key (ED25519)".into()));
+        let json = serde_json::to_string(&inspection).expect("json");
+        assert!(!json.contains("very-secret"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn flag_missing_and_malformed_entries() {
+        let path = write_zip("inspect-broken.zip", &[
+            ("treehub.json", br#"{"ostree": {}}"#),
+            ("api_gateway.url", b"not a url"),
+            ("client.pem", b"garbage"),
+        ]);
+
+        let inspection = Inspection::inspect(&path).expect("inspect");
+        assert!(!inspection.is_ok());
+        assert_eq!(inspection.auth, None);
+        for problem in &["missing tufrepo.url", "missing ostree.server", "malformed api_gateway.url", "malformed client.pem"] {
+            assert!(inspection.problems.iter().any(|p| p.starts_with(problem)), "{}: {:?}", problem, inspection.problems);
+        }
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,5 +1,7 @@
+use openssl;
 use reqwest;
 use serde_json;
+use serde_yaml;
 use std::{
     self,
     fmt::{self, Debug, Display, Formatter},
@@ -15,19 +17,27 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 /// Conversion from app or lib errors to a single representation.
 pub enum Error {
+    Api(Box<ApiError>),
     Args(String),
     Auth(String),
     Command(String),
+    Config(String),
+    Crypto(String),
+    DryRun(String),
     NotFound(String, Option<String>),
     Parse(String),
+    Pin(String),
+    Query(String),
     Token(String),
 
     Http(reqwest::Error),
     Io(std::io::Error),
     Json(serde_json::Error),
+    Tls(openssl::error::ErrorStack),
     Toml(toml::de::Error),
     Url(url::ParseError),
     Uuid(uuid::parser::ParseError),
+    Yaml(serde_yaml::Error),
     Zip(zip::result::ZipError),
 }
 
@@ -35,28 +45,57 @@ impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         #[cfg_attr(rustfmt, rustfmt_skip)]
         let output = match self {
+            Error::Api(err)     => format!("API: {}", err),
             Error::Args(err)    => format!("Command args: {}", err),
             Error::Auth(err)    => format!("Authorization: {}", err),
             Error::Command(err) => format!("Command input: {}", err),
+            Error::Config(err)  => format!("Config: {}", err),
+            Error::Crypto(err)  => format!("Encryption: {}", err),
+            Error::DryRun(req)  => format!("Dry run: stopped after `{}` because the command needs its response", req),
             Error::NotFound(name, help) => match help {
                 Some(help) => format!("{} not found. {}", name, help),
                 None       => format!("{} not found.", name)
             },
             Error::Parse(err)   => format!("Parse error: {}", err),
+            Error::Pin(err)     => format!("Certificate pinning: {}", err),
+            Error::Query(err)   => format!("Query: {}", err),
             Error::Token(err)   => format!("Parsing access token: {}", err),
 
             Error::Http(err)    => format!("HTTP: {}", err),
             Error::Io(err)      => format!("I/O: {}", err),
             Error::Json(err)    => format!("Parsing JSON: {}", err),
+            Error::Tls(err)     => format!("TLS: {}", err),
             Error::Toml(err)    => format!("Parsing TOML: {}", err),
             Error::Url(err)     => format!("Parsing URL: {}", err),
             Error::Uuid(err)    => format!("Parsing UUID: {}", err),
+            Error::Yaml(err)    => format!("Writing YAML: {}", err),
             Error::Zip(err)     => format!("Zip I/O: {}", err),
         };
         write!(f, "{}", output)
     }
 }
 
+impl Error {
+    /// Map each class of error to a distinct process exit code.
+    pub fn exit_code(&self) -> i32 {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match self {
+            Error::DryRun(_) => 9,
+            Error::Args(_) | Error::Command(_) | Error::Parse(_) | Error::Query(_) | Error::Uuid(_) => 2,
+            Error::Config(_) | Error::Crypto(_) | Error::NotFound(..) | Error::Toml(_) => 3,
+            Error::Auth(_) | Error::Token(_) => 4,
+            Error::Api(err) => match err.status {
+                401 | 403 => 4,
+                404       => 5,
+                400..=499 => 6,
+                _         => 7,
+            },
+            Error::Http(_) | Error::Pin(_) | Error::Tls(_) => 8,
+            Error::Io(_) | Error::Json(_) | Error::Url(_) | Error::Yaml(_) | Error::Zip(_) => 1,
+        }
+    }
+}
+
 impl Debug for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "{}", self) }
 }
@@ -65,6 +104,63 @@ impl std::error::Error for Error {
     fn description(&self) -> &str { "ota-cli error" }
 }
 
+/// A non-2xx response from a server endpoint.
+pub struct ApiError {
+    pub status:      u16,
+    pub code:        Option<String>,
+    pub description: Option<String>,
+    pub method:      String,
+    pub url:         String,
+    /// Seconds to wait from a `Retry-After` header.
+    pub retry_after: Option<u64>,
+    /// The `x-request-id` sent with the request.
+    pub request_id:  Option<String>,
+}
+
+impl ApiError {
+    /// Parse the `code` and `description` from an ATS JSON error body, or else
+    /// use the body as the description.
+    pub fn new(status: u16, method: impl Into<String>, url: impl Into<String>, body: &str) -> Self {
+        let (code, description) = match serde_json::from_str::<serde_json::Value>(body) {
+            Ok(json) => {
+                let field = |name| json.get(name).and_then(|val| val.as_str()).map(String::from);
+                (field("code"), field("description"))
+            }
+            Err(_) if body.trim().is_empty() => (None, None),
+            Err(_) => (None, Some(body.trim().into())),
+        };
+        ApiError {
+            status,
+            code,
+            description,
+            method: method.into(),
+            url: url.into(),
+            retry_after: None,
+            request_id: None,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {} returned {}", self.method, self.url, self.status)?;
+        if let Some(ref code) = self.code {
+            write!(f, " ({})", code)?;
+        }
+        if let Some(ref description) = self.description {
+            write!(f, ": {}", description)?;
+        }
+        if let Some(ref request_id) = self.request_id {
+            write!(f, " [request id {}]", request_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ApiError> for Error {
+    fn from(err: ApiError) -> Self { Error::Api(Box::new(err)) }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self { Error::Http(err) }
 }
@@ -77,6 +173,10 @@ impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self { Error::Json(err) }
 }
 
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(err: openssl::error::ErrorStack) -> Self { Error::Tls(err) }
+}
+
 impl From<toml::de::Error> for Error {
     fn from(err: toml::de::Error) -> Self { Error::Toml(err) }
 }
@@ -89,6 +189,10 @@ impl From<uuid::parser::ParseError> for Error {
     fn from(err: uuid::parser::ParseError) -> Self { Error::Uuid(err) }
 }
 
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self { Error::Yaml(err) }
+}
+
 impl From<zip::result::ZipError> for Error {
     fn from(err: zip::result::ZipError) -> Self { Error::Zip(err) }
 }
@@ -1,25 +1,36 @@
-use reqwest::{Client, RequestBuilder, Response, Url};
-use serde_json::{self, Value};
-use std::io::{self, Read};
+use openssl::x509::X509;
+use reqwest::{Certificate, Client, Identity, Method, Proxy};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+    cmp,
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use uuid::Uuid;
 
-use api::auth_plus::AccessToken;
-use error::{Error, Result};
+use api::auth_plus::{AccessToken, ClientTls};
+use cassette::RecordingTransport;
+use config::Config;
+use error::{ApiError, Error, Result};
+use pinned::PinnedTransport;
+use rate_limit::RateLimitedTransport;
+use trace::TraceTransport;
+use transport::{redact_headers, DryRunTransport, ReqwestTransport, Request, Response, Transport};
 
 
+/// The header that identifies each request in the server logs.
+pub const REQUEST_ID: &str = "x-request-id";
+
 /// Convenience methods for making simple HTTP requests.
 pub trait HttpMethods {
-    fn get(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().get(Url::parse(url.as_ref())?), token)
-    }
-    fn post(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().post(Url::parse(url.as_ref())?), token)
-    }
-    fn put(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().put(Url::parse(url.as_ref())?), token)
-    }
-    fn delete(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().delete(Url::parse(url.as_ref())?), token)
-    }
+    fn get(url: impl AsRef<str>, config: &mut Config) -> Result<Response> { Http::send(Request::get(url)?, config) }
+    fn post(url: impl AsRef<str>, config: &mut Config) -> Result<Response> { Http::send(Request::post(url)?, config) }
+    fn put(url: impl AsRef<str>, config: &mut Config) -> Result<Response> { Http::send(Request::put(url)?, config) }
+    fn delete(url: impl AsRef<str>, config: &mut Config) -> Result<Response> { Http::send(Request::delete(url)?, config) }
 }
 
 
@@ -29,42 +40,529 @@ pub struct Http;
 impl HttpMethods for Http {}
 
 impl Http {
+    /// Send an HTTP request authenticated with the config's access token.
+    ///
+    /// The request is retried once with a fresh token after a 401 response.
+    /// Idempotent requests are also retried on transient failures according
+    /// to the config's `RetryPolicy`. Any other non-2xx response is returned
+    /// as an `Error::Api`.
+    ///
+    /// With `config.dry_run` set, the request is printed and a synthetic
+    /// `204 No Content` response is returned instead. The response has no
+    /// body, so reading it as JSON returns `Error::DryRun`.
+    pub fn send(req: Request, config: &mut Config) -> Result<Response> { Self::send_with(req, config, false) }
+
+    /// Send a non-idempotent request that is safe to repeat, retrying it on
+    /// transient failures like an idempotent request.
+    pub fn send_repeatable(req: Request, config: &mut Config) -> Result<Response> { Self::send_with(req, config, true) }
+
+    fn send_with(mut req: Request, config: &mut Config, repeatable: bool) -> Result<Response> {
+        let mut transport = Self::transport(config)?;
+        if let Some(options) = config.dry_run {
+            transport = Arc::new(DryRunTransport::new(transport, options));
+        }
+        let mut token = config.token()?;
+        if let Some(namespace) = config.namespace(token.as_ref())? {
+            req = req.header("x-ats-namespace", namespace);
+        }
+
+        let retryable = repeatable || is_idempotent(&req.method);
+        let policy = config.retry.clone();
+        let mut refreshed = false;
+        let mut attempt = 0;
+
+        loop {
+            match Self::execute(&*transport, &req, token.as_ref()) {
+                Err(Error::Api(ref err)) if err.status == 401 && token.is_some() && !refreshed => {
+                    debug!("request unauthorized, retrying with a new access token...");
+                    token = config.refresh_token()?;
+                    refreshed = true;
+                }
+                Err(ref err) if retryable && attempt < policy.max_retries && is_transient(err) => {
+                    let delay = policy.delay(attempt, err);
+                    attempt += 1;
+                    debug!("retry {}/{} in {}ms after error: {}", attempt, policy.max_retries, millis(delay), err);
+                    thread::sleep(delay);
+                }
+                resp => return resp,
+            }
+        }
+    }
+
+    /// Return the transport shared by every request in this invocation,
+    /// building a network transport on first use.
+    ///
+    /// The client presents any TLS client identity from the credentials and
+    /// applies the timeouts, proxy, CA bundles, pins and rate limits from the
+    /// config. With `config.trace_file` or `config.record` set, each exchange
+    /// is also saved as a HAR entry or a cassette.
+    pub fn transport(config: &mut Config) -> Result<Arc<dyn Transport>> {
+        if let Some(ref transport) = config.transport {
+            return Ok(transport.clone());
+        }
+
+        let settings = config.http.clone();
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+            .timeout(Duration::from_secs(settings.read_timeout_secs));
+        if let Some(ref proxy) = settings.proxy {
+            debug!("using proxy {}", proxy);
+            builder = builder.proxy(Proxy::all(proxy.as_str())?);
+        }
+        for bundle in &settings.ca_bundles {
+            debug!("trusting ca bundle {:?}", bundle);
+            for cert in X509::stack_from_pem(&fs::read(bundle)?)? {
+                builder = builder.add_root_certificate(Certificate::from_der(&cert.to_der()?)?);
+            }
+        }
+        let tls = Self::client_tls(config)?;
+        if let Some(ref tls) = tls {
+            debug!("using tls client identity from credentials.zip");
+            builder = builder.identity(Identity::from_pkcs12_der(&tls.pkcs12, "")?);
+            if let Some(ref server_ca) = tls.server_ca {
+                builder = builder.add_root_certificate(Certificate::from_pem(server_ca)?);
+            }
+        }
+
+        let mut transport: Arc<dyn Transport> = Arc::new(ReqwestTransport::new(builder.build()?));
+        if !settings.pins.is_empty() {
+            let mut pins = BTreeMap::new();
+            for (service, pin) in &settings.pins {
+                debug!("pinning {} to {}", service, pin);
+                let url = config.service_url(service)?;
+                let address = format!("{}:{}", url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or(443));
+                let _ = pins.insert(address, (service.clone(), pin.clone()));
+            }
+            transport = Arc::new(PinnedTransport::new(transport, settings.clone(), tls, pins));
+        }
+        if !config.rate_limits.is_empty() {
+            let mut limits = Vec::new();
+            for (service, limit) in config.rate_limits.clone() {
+                debug!("limiting {} to {} requests per second", service, limit.per_second);
+                limits.push((config.service_url(&service)?, service, limit));
+            }
+            transport = Arc::new(RateLimitedTransport::new(transport, limits));
+        }
+        if let Some(ref path) = config.trace_file {
+            debug!("tracing requests to {:?}", path);
+            transport = Arc::new(TraceTransport::new(transport, path.clone()));
+        }
+        if let Some(ref dir) = config.record {
+            debug!("recording cassettes to {:?}", dir);
+            transport = Arc::new(RecordingTransport::new(transport, dir.clone())?);
+        }
+        config.transport = Some(transport.clone());
+        Ok(transport)
+    }
+
+    /// Return the TLS client identity from the credentials, if any.
+    fn client_tls(config: &mut Config) -> Result<Option<ClientTls>> {
+        let static_token = config.static_token;
+        match config.credentials() {
+            Ok(credentials) => Ok(credentials.tls().cloned()),
+            Err(ref err) if static_token => {
+                debug!("skipping credentials for client tls: {}", err);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     /// Send an HTTP request with an optional bearer token.
-    pub fn send(mut builder: RequestBuilder, token: Option<AccessToken>) -> Result<Response> {
+    ///
+    /// Each request is sent with a new `x-request-id` header. A non-2xx
+    /// response is returned as an `Error::Api`.
+    pub fn execute(transport: &dyn Transport, req: &Request, token: Option<&AccessToken>) -> Result<Response> {
+        let request_id = new_request_id()?;
+        let mut req = req.clone().header(REQUEST_ID, request_id.as_str());
         if let Some(token) = token {
             debug!("request with token scopes: {}", token.scope);
-            builder = builder.bearer_auth(token.access_token.clone());
+            req = req.bearer_auth(&token.access_token);
+        }
+        debug!("request headers:\n{:#?}", redact_headers(&req.headers));
+        if let Some(ref body) = req.body {
+            debug!("request body:\n{:?}\n", body);
+        }
+
+        let resp = transport.execute(&req)?;
+        debug!("{} {} returned {} [request id {}]", req.method, req.url, resp.status(), request_id);
+        if resp.status().is_success() {
+            Ok(resp)
+        } else {
+            let body = resp.text();
+            debug!("error response body:\n{}", body);
+            let mut err = ApiError::new(resp.status().as_u16(), req.method.as_str(), req.url.as_str(), &body);
+            err.retry_after = resp.header("retry-after").and_then(|val| val.trim().parse().ok());
+            err.request_id = Some(request_id);
+            Err(err)?
+        }
+    }
+}
+
+
+/// Settings for building the shared HTTP client.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs:    u64,
+    /// A proxy URL for all requests.
+    pub proxy: Option<String>,
+    /// PEM files with extra CA certificates to trust.
+    pub ca_bundles: Vec<PathBuf>,
+    /// Service names mapped to `sha256/<base64>` pins of the server public key.
+    pub pins: BTreeMap<String, String>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            connect_timeout_secs: 10,
+            read_timeout_secs:    30,
+            proxy:                None,
+            ca_bundles:           Vec::new(),
+            pins:                 BTreeMap::new(),
+        }
+    }
+}
+
+
+/// Limits on retrying transient request failures.
+///
+/// Each retry waits for a random delay between half and all of
+/// `base_delay_ms * 2^attempt`, capped at `max_delay_ms`. A `Retry-After`
+/// header in seconds replaces the computed delay, within the same cap.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retries:   u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms:  u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries:   3,
+            base_delay_ms: 500,
+            max_delay_ms:  30_000,
+        }
+    }
+}
 
-            match token.namespace() {
-                Ok(name) => builder = builder.header("x-ats-namespace", name),
-                Err(err) => error!("reading token namespace: {}", err),
+impl RetryPolicy {
+    /// The delay before retrying after the failed `attempt` (starting at 0).
+    pub fn delay(&self, attempt: u32, err: &Error) -> Duration {
+        let max = Duration::from_millis(self.max_delay_ms);
+        if let Error::Api(ref err) = err {
+            if let Some(secs) = err.retry_after {
+                return cmp::min(Duration::from_secs(secs), max);
             }
         }
 
-        let req = builder.build()?;
-        if req.headers().len() > 0 {
-            debug!("request headers:\n{:#?}", req.headers());
+        let backoff = self.base_delay_ms.saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX));
+        let backoff = cmp::min(backoff, self.max_delay_ms);
+        let mut random = [0u8; 8];
+        let jitter = match SystemRandom::new().fill(&mut random) {
+            Ok(()) => u64::from_le_bytes(random) % (backoff / 2 + 1),
+            Err(_) => 0,
+        };
+        Duration::from_millis(backoff - jitter)
+    }
+}
+
+/// Whether repeating the request has the same effect as sending it once.
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE)
+}
+
+/// Whether the error is a connection failure or a temporary server condition.
+fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::Http(err) => err.is_http() || err.is_timeout(),
+        Error::Api(err) => matches!(err.status, 429 | 502 | 503 | 504),
+        _ => false,
+    }
+}
+
+/// Return a random UUID to correlate a request with the server logs.
+fn new_request_id() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| Error::Crypto("no random bytes for request id".into()))?;
+    Ok(Uuid::from_random_bytes(bytes).to_string())
+}
+
+/// The duration in whole milliseconds.
+pub(crate) fn millis(duration: Duration) -> u64 { duration.as_secs() * 1000 + u64::from(duration.subsec_millis()) }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        asn1::Asn1Time,
+        base64,
+        bn::BigNum,
+        hash::{hash, MessageDigest},
+        nid::Nid,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        ssl::{SslAcceptor, SslMethod, SslVerifyMode},
+        x509::{
+            extension::{BasicConstraints, SubjectAlternativeName},
+            X509Builder,
+            X509NameBuilder,
+            X509,
+        },
+    };
+    use reqwest::{StatusCode, Url};
+    use std::{
+        env,
+        fs::File,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        path::PathBuf,
+        process,
+        thread,
+    };
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+
+    fn private_key() -> PKey<Private> { PKey::from_rsa(Rsa::generate(2048).expect("rsa")).expect("pkey") }
+
+    /// Create a certificate signed by the `issuer`, or a self-signed CA certificate.
+    fn certificate(name: &str, key: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>) -> X509 {
+        let mut subject = X509NameBuilder::new().expect("name");
+        subject.append_entry_by_text("CN", name).expect("cn");
+        let subject = subject.build();
+
+        let mut builder = X509Builder::new().expect("builder");
+        builder.set_version(2).expect("version");
+        let serial = BigNum::from_u32(name.len() as u32).and_then(|num| num.to_asn1_integer()).expect("serial");
+        builder.set_serial_number(&serial).expect("serial");
+        builder.set_subject_name(&subject).expect("subject");
+        builder.set_pubkey(key).expect("pubkey");
+        builder.set_not_before(&Asn1Time::days_from_now(0).expect("time")).expect("not before");
+        builder.set_not_after(&Asn1Time::days_from_now(1).expect("time")).expect("not after");
+
+        match issuer {
+            Some((ca, ca_key)) => {
+                builder.set_issuer_name(ca.subject_name()).expect("issuer");
+                let names = SubjectAlternativeName::new()
+                    .ip("127.0.0.1")
+                    .build(&builder.x509v3_context(Some(ca), None))
+                    .expect("san");
+                builder.append_extension(names).expect("san");
+                builder.sign(ca_key, MessageDigest::sha256()).expect("sign");
+            }
+            None => {
+                builder.set_issuer_name(&subject).expect("issuer");
+                builder.append_extension(BasicConstraints::new().critical().ca().build().expect("ca")).expect("ca");
+                builder.sign(key, MessageDigest::sha256()).expect("sign");
+            }
         }
-        if let Some(body) = req.body() {
-            debug!("request body:\n{:?}\n", body);
+        builder.build()
+    }
+
+    /// Write a credentials.zip without an OAuth2 section.
+    fn credentials_zip(files: &[(&str, Vec<u8>)]) -> PathBuf {
+        let path = env::temp_dir().join(format!("ota-{}-credentials.zip", process::id()));
+        let mut zip = ZipWriter::new(File::create(&path).expect("create zip"));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("treehub.json", options).expect("treehub.json");
+        zip.write_all(br#"{"ostree": {"server": "https://127.0.0.1/"}}"#).expect("treehub.json");
+        for (name, contents) in files {
+            zip.start_file(*name, options).expect("zip file");
+            zip.write_all(contents).expect("zip file");
         }
+        let _ = zip.finish().expect("finish zip");
+        path
+    }
 
-        Client::new().execute(req).map_err(Error::Http)
+    /// Serve plain HTTP responses in order, returning the request lines.
+    fn serve(responses: &'static [&'static str]) -> (Url, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}/", listener.local_addr().expect("addr")).parse().expect("url");
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().expect("accept");
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                let mut request = String::new();
+                while reader.read_line(&mut line).expect("read request") > 2 {
+                    if request.is_empty() {
+                        request = line.trim().into();
+                    }
+                    line.clear();
+                }
+                reader.into_inner().write_all(response.as_bytes()).expect("write response");
+                requests.push(request);
+            }
+            requests
+        });
+        (url, handle)
     }
 
-    /// Print the HTTP response to stdout.
-    pub fn print_response(mut resp: Response) -> Result<()> {
-        let mut body = Vec::new();
-        debug!("response headers:\n{:#?}", resp.headers());
-        debug!("response length: {}\n", resp.read_to_end(&mut body)?);
+    #[test]
+    fn api_error_from_response() {
+        let (url, handle) = serve(&[concat!(
+            "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 57\r\nConnection: close\r\n\r\n",
+            r#"{"code":"missing_entity","description":"Group not found"}"#
+        )]);
+        let mut config = Config::for_test(&url, PathBuf::from("missing.zip"));
+        config.static_token = true;
 
-        let out = if let Ok(json) = serde_json::from_slice::<Value>(&body) {
-            serde_json::to_vec_pretty(&json)?
-        } else {
-            body
-        };
+        match Http::delete(format!("{}api/v1/device_groups/1", url), &mut config) {
+            Err(Error::Api(err)) => {
+                assert_eq!(err.status, 404);
+                assert_eq!(err.code, Some("missing_entity".into()));
+                assert_eq!(err.description, Some("Group not found".into()));
+                assert_eq!(err.method, "DELETE");
+                assert_eq!(err.url, format!("{}api/v1/device_groups/1", url));
+                assert_eq!(err.request_id.as_ref().map(String::len), Some(36));
+                assert_eq!(Error::Api(err).exit_code(), 5);
+            }
+            other => panic!("expected api error, got {:?}", other.map(|resp| resp.status())),
+        }
+        assert_eq!(handle.join().expect("server"), vec!["DELETE /api/v1/device_groups/1 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        const BAD_GATEWAY: &str = "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+        let (url, handle) = serve(&[UNAVAILABLE, BAD_GATEWAY, OK, BAD_GATEWAY]);
+        let mut config = Config::for_test(&url, PathBuf::from("missing.zip"));
+        config.static_token = true;
+        config.retry.base_delay_ms = 1;
+
+        let resp = Http::get(format!("{}api/v1/devices", url), &mut config).expect("response");
+        assert_eq!(resp.text(), "ok");
+        match Http::post(format!("{}api/v2/campaigns", url), &mut config) {
+            Err(Error::Api(ref err)) if err.status == 502 => (),
+            other => panic!("expected no retry for POST, got {:?}", other.map(|resp| resp.status())),
+        }
+        assert_eq!(handle.join().expect("server").len(), 4);
+    }
+
+    #[test]
+    fn retry_delay_backoff() {
+        let policy = RetryPolicy::default();
+        let err = Error::Args("test".into());
+        for attempt in 0..10 {
+            let backoff = cmp::min(500 << attempt, 30_000);
+            let delay = millis(policy.delay(attempt, &err));
+            assert!(delay >= backoff / 2 && delay <= backoff, "attempt {}: {}ms", attempt, delay);
+        }
+
+        let mut api = ApiError::new(503, "GET", "http://localhost/", "");
+        api.retry_after = Some(120);
+        assert_eq!(policy.delay(0, &Error::from(api)), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn client_certificate_handshake() {
+        let ca_key = private_key();
+        let ca = certificate("ota-ca", &ca_key, None);
+        let server_key = private_key();
+        let server = certificate("ota-server", &server_key, Some((&ca, &ca_key)));
+        let client_key = private_key();
+        let client = certificate("ota-client", &client_key, Some((&ca, &ca_key)));
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).expect("acceptor");
+        acceptor.set_private_key(&server_key).expect("server key");
+        acceptor.set_certificate(&server).expect("server cert");
+        acceptor.cert_store_mut().add_cert(ca.clone()).expect("ca");
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = acceptor.build();
 
-        let _ = io::copy(&mut out.as_slice(), &mut io::stdout())?;
-        Ok(())
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            let stream = acceptor.accept(stream).expect("tls handshake");
+            let peer = stream.ssl().peer_certificate().expect("client certificate");
+            let name = peer.subject_name().entries_by_nid(Nid::COMMONNAME).next().expect("cn").data().to_string().expect("utf8");
+
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).expect("read request") > 2 {
+                line.clear();
+            }
+            let mut stream = reader.into_inner();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").expect("write response");
+            name
+        });
+
+        let credentials_zip = credentials_zip(&[
+            ("client.pem", client.to_pem().expect("pem")),
+            ("pkey.pem", client_key.private_key_to_pem_pkcs8().expect("pem")),
+            ("server_ca.pem", ca.to_pem().expect("pem")),
+        ]);
+        let url: Url = format!("https://127.0.0.1:{}/", port).parse().expect("url");
+        let mut config = Config::for_test(&url, credentials_zip.clone());
+
+        let resp = Http::get(url, &mut config).expect("response");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text(), "ok");
+        assert_eq!(handle.join().expect("server"), "ota-client");
+        let _ = ::std::fs::remove_file(credentials_zip);
+    }
+
+    #[test]
+    fn pinned_server_key_and_ca_bundle() {
+        let ca_key = private_key();
+        let ca = certificate("ota-ca", &ca_key, None);
+        let server_key = private_key();
+        let server = certificate("ota-server", &server_key, Some((&ca, &ca_key)));
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).expect("acceptor");
+        acceptor.set_private_key(&server_key).expect("server key");
+        acceptor.set_certificate(&server).expect("server cert");
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        let handle = thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().expect("accept");
+                let stream = match acceptor.accept(stream) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                    line.clear();
+                }
+                let _ = reader.into_inner().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+            }
+        });
+
+        let ca_bundle = env::temp_dir().join(format!("ota-{}-ca-bundle.pem", process::id()));
+        ::std::fs::write(&ca_bundle, ca.to_pem().expect("pem")).expect("write ca bundle");
+        let digest = hash(MessageDigest::sha256(), &server_key.public_key_to_der().expect("der")).expect("digest");
+        let pin = format!("sha256/{}", base64::encode_block(&digest));
+
+        let url: Url = format!("https://127.0.0.1:{}/", port).parse().expect("url");
+        let mut config = Config::for_test(&url, PathBuf::from("missing.zip"));
+        config.static_token = true;
+        config.http.ca_bundles = vec![ca_bundle.clone()];
+        let _ = config.http.pins.insert("campaigner".into(), pin);
+        let resp = Http::get(format!("{}api/v2/campaigns", url), &mut config).expect("response");
+        assert_eq!(resp.text(), "ok");
+
+        let mut config = Config::for_test(&url, PathBuf::from("missing.zip"));
+        config.static_token = true;
+        config.http.ca_bundles = vec![ca_bundle.clone()];
+        let _ = config.http.pins.insert("campaigner".into(), "sha256/AAAA".into());
+        match Http::get(format!("{}api/v2/campaigns", url), &mut config) {
+            Err(Error::Pin(ref err)) if err.contains("presented key") => (),
+            other => panic!("expected pin error, got {:?}", other.map(|resp| resp.status())),
+        }
+        handle.join().expect("server");
+        let _ = ::std::fs::remove_file(ca_bundle);
     }
 }
@@ -0,0 +1,193 @@
+use clap::ArgMatches;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use config::Config;
+use error::{Error, Result};
+use http::Http;
+
+
+/// The number of concurrent jobs when `--jobs` is not set.
+pub const DEFAULT_JOBS: usize = 1;
+
+/// Parse the `--jobs` arg.
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<usize> {
+    match args.value_of("jobs") {
+        Some(val) => match val.parse() {
+            Ok(jobs) if jobs > 0 => Ok(jobs),
+            _ => Err(Error::Args("--jobs must be a positive number".into())),
+        },
+        None => Ok(DEFAULT_JOBS),
+    }
+}
+
+/// Run `task` for each item with up to `jobs` requests in flight.
+///
+/// Every worker shares the config's transport and access token, so an expired
+/// token is refreshed and saved by one worker while the others wait. Each item
+/// is attempted and the results are returned in the same order as `items`.
+///
+/// The workers are threads rather than async tasks because the blocking
+/// reqwest client is the only one available at this version.
+pub fn run_all<T, R, F>(config: &mut Config, jobs: usize, items: Vec<T>, task: F) -> Result<Vec<Result<R>>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(&mut Config, T) -> Result<R> + Sync,
+{
+    let _ = Http::transport(config)?;
+    let _ = config.token()?;
+    let owner = config.shared_token.is_none();
+    if owner {
+        config.shared_token = Some(Arc::new(Mutex::new(config.token.clone())));
+    }
+
+    let total = items.len();
+    let workers = jobs.min(total).max(1);
+    debug!("running {} jobs on {} workers", total, workers);
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..total).map(|_| None).collect::<Vec<Option<Result<R>>>>());
+    let done = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let mut config = config.clone();
+            let (queue, results, done, task) = (&queue, &results, &done, &task);
+            let _ = scope.spawn(move || loop {
+                let next = queue.lock().expect("job queue lock").next();
+                let (index, item) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                let result = task(&mut config, item);
+                if let Err(ref err) = result {
+                    warn!("job {} failed: {}", index + 1, err);
+                }
+                results.lock().expect("job results lock")[index] = Some(result);
+                debug!("finished {}/{} jobs", done.fetch_add(1, Ordering::SeqCst) + 1, total);
+            });
+        }
+    });
+
+    if owner {
+        if let Some(shared) = config.shared_token.take() {
+            config.token = shared.lock().expect("token lock").clone();
+        }
+    }
+    Ok(results
+        .into_inner()
+        .expect("job results lock")
+        .into_iter()
+        .map(|result| result.expect("job result"))
+        .collect())
+}
+
+/// Return the last value, or the first error after reporting how many failed.
+pub fn last_ok<R>(results: Vec<Result<R>>) -> Result<R> {
+    let total = results.len();
+    let mut last = None;
+    let mut failed = Vec::new();
+    for result in results {
+        match result {
+            Ok(val) => last = Some(val),
+            Err(err) => failed.push(err),
+        }
+    }
+    if failed.len() > 1 {
+        error!("{} of {} jobs failed", failed.len(), total);
+    }
+    match (failed.into_iter().next(), last) {
+        (Some(err), _) => Err(err),
+        (None, Some(last)) => Ok(last),
+        (None, None) => Err(Error::Args("nothing to do".into())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{StatusCode, Url};
+    use std::{
+        sync::mpsc,
+        time::{Duration, Instant},
+    };
+
+    use api::auth_plus::{AccessToken, Credentials};
+    use transport::{Request, Response, Transport};
+
+
+    struct SlowTransport;
+
+    impl Transport for SlowTransport {
+        fn execute(&self, req: &Request) -> Result<Response> {
+            thread::sleep(Duration::from_millis(100));
+            Ok(Response::new(StatusCode::OK, req.url.path()))
+        }
+    }
+
+    #[test]
+    fn run_jobs_concurrently_in_order() {
+        let url = Url::parse("http://registry").expect("url");
+        let mut config = Config::for_test(&url, "credentials.zip".into());
+        config.static_token = true;
+        config.transport = Some(Arc::new(SlowTransport));
+
+        let items = (0..8).collect::<Vec<_>>();
+        let start = Instant::now();
+        let results = run_all(&mut config, 4, items, |config, item| {
+            if item == 5 {
+                Err(Error::Args("five".into()))?
+            }
+            Http::send(Request::get(format!("http://registry/{}", item))?, config).map(|resp| resp.text())
+        })
+        .expect("run");
+        assert!(start.elapsed() < Duration::from_millis(500), "{:?}", start.elapsed());
+
+        assert_eq!(results.len(), 8);
+        assert_eq!(results[0].as_ref().expect("first"), "/0");
+        assert_eq!(results[7].as_ref().expect("last"), "/7");
+        match last_ok(results) {
+            Err(Error::Args(ref msg)) if msg == "five" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refresh_shared_token_in_jobs() {
+        let url = Url::parse("http://registry").expect("url");
+        let credentials: Credentials =
+            serde_json::from_value(json!({"no_auth": true, "ostree": {"server": "http://treehub/"}})).expect("credentials");
+        let expired: AccessToken = serde_json::from_value(json!({
+            "access_token": "expired", "token_type": "bearer", "expires_in": 0, "scope": "", "issued_at": 0
+        }))
+        .expect("token");
+
+        for token in vec![None, Some(expired)] {
+            let mut config = Config::for_test(&url, "credentials.zip".into());
+            config.credentials = Some(credentials.clone());
+            config.token = token;
+            config.transport = Some(Arc::new(SlowTransport));
+
+            let (sender, receiver) = mpsc::channel();
+            let _ = thread::spawn(move || {
+                let results = run_all(&mut config, 2, vec![1, 2, 3], |config, item| {
+                    let _ = config.token()?;
+                    let _ = config.refresh_token()?;
+                    Ok(item)
+                });
+                let _ = sender.send(results.map(|results| results.len()));
+            });
+            match receiver.recv_timeout(Duration::from_secs(5)) {
+                Ok(Ok(done)) => assert_eq!(done, 3),
+                Ok(Err(err)) => panic!("jobs failed: {}", err),
+                Err(_) => panic!("jobs deadlocked on the shared token"),
+            }
+        }
+    }
+}
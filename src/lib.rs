@@ -2,14 +2,17 @@ extern crate clap;
 extern crate dirs;
 #[macro_use]
 extern crate log;
+extern crate openssl;
 extern crate pretty_env_logger;
 extern crate reqwest;
+extern crate ring;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate serde_urlencoded;
+extern crate serde_yaml;
 extern crate toml;
 extern crate url;
 extern crate url_serde;
@@ -18,7 +21,17 @@ extern crate uuid;
 extern crate zip;
 
 pub mod api;
+pub mod cassette;
 pub mod command;
 pub mod config;
+pub mod credentials;
 pub mod error;
 pub mod http;
+pub mod jobs;
+pub mod output;
+pub mod pinned;
+pub mod query;
+pub mod rate_limit;
+pub mod token_store;
+pub mod trace;
+pub mod transport;
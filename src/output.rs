@@ -0,0 +1,546 @@
+use clap::ArgMatches;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde_json::{self, Value};
+use serde_yaml;
+use std::{
+    io::{self, Write},
+    result,
+    str::FromStr,
+};
+use uuid::Uuid;
+
+use error::{Error, Result};
+use query::Query;
+
+
+/// Available output formats.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    Yaml,
+    Csv,
+    Table,
+    Ndjson,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "json"   => Ok(Format::Json),
+            "yaml"   => Ok(Format::Yaml),
+            "csv"    => Ok(Format::Csv),
+            "table"  => Ok(Format::Table),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(Error::Args(format!("unknown output format: {} (expected json, yaml, csv, table or ndjson)", s))),
+        }
+    }
+}
+
+
+/// A resource that can be printed as rows of a table.
+pub trait Render: Serialize {
+    /// The default columns for tables and CSV, as JSON field names. When
+    /// empty, the fields of the first row are used.
+    fn columns() -> &'static [&'static str] { &[] }
+
+    /// The rows to print for tables, CSV and NDJSON.
+    fn rows(&self) -> Result<Vec<Value>> { Ok(vec![serde_json::to_value(self)?]) }
+
+    /// Whether the rows are items of a list rather than a single value.
+    fn is_list() -> bool { false }
+
+    /// The field that identifies each row, printed alone with `--quiet`.
+    fn id() -> &'static str { "id" }
+}
+
+impl<T: Render> Render for Vec<T> {
+    fn columns() -> &'static [&'static str] { T::columns() }
+
+    fn is_list() -> bool { true }
+
+    fn id() -> &'static str { T::id() }
+
+    fn rows(&self) -> Result<Vec<Value>> { rows(self) }
+}
+
+impl Render for Uuid {
+    fn columns() -> &'static [&'static str] { &["id"] }
+
+    fn rows(&self) -> Result<Vec<Value>> { Ok(vec![json!({ "id": self })]) }
+}
+
+/// Collect the rows of each value.
+pub fn rows<T: Render>(values: &[T]) -> Result<Vec<Value>> {
+    let mut rows = Vec::new();
+    for value in values {
+        rows.extend(value.rows()?);
+    }
+    Ok(rows)
+}
+
+
+/// Print command results to stdout in the selected format.
+#[derive(Clone, Debug, Default)]
+pub struct Output {
+    pub format: Format,
+    /// Fields to select from each item, as query paths.
+    pub fields: Vec<Query>,
+    /// A query to apply to the result before printing.
+    pub query:  Option<Query>,
+    /// Print only the ID of each item, one per line.
+    pub quiet:  bool,
+}
+
+impl<'a> Output {
+    /// Parse the `--output`, `--fields`, `--query` and `--quiet` args.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
+        Ok(Output {
+            format: match args.value_of("output") {
+                Some(format) => format.parse()?,
+                None => Format::default(),
+            },
+            fields: match args.value_of("fields") {
+                Some(fields) => fields.split(',').map(|field| field.trim().parse()).collect::<Result<_>>()?,
+                None => Vec::new(),
+            },
+            query:  match args.value_of("query") {
+                Some(query) => Some(query.parse()?),
+                None => None,
+            },
+            quiet:  args.is_present("quiet"),
+        })
+    }
+
+    /// Print a single value.
+    pub fn print<T: Render>(&self, value: &T) -> Result<()> {
+        if self.quiet {
+            return write_ids(&mut io::stdout().lock(), &value.rows()?, T::id());
+        }
+        match self.query {
+            Some(ref query) => self.print_result(query.apply(&serde_json::to_value(value)?)?),
+            None if !self.fields.is_empty() => self.print_rows(value.rows()?, T::is_list(), T::columns()),
+            None => {
+                let rows = value.rows()?;
+                self.write(value, &rows, &rows, T::columns())
+            }
+        }
+    }
+
+    /// Stream the values from every page as one list. Tables are aligned
+    /// across every page, and a query reads the whole list, so these are
+    /// written once the last page arrives. The query reads the values as
+    /// `{"values": [...]}`, the same shape as a single page.
+    pub fn print_pages<T, I>(&self, pages: I) -> Result<()>
+    where
+        T: Render,
+        I: Iterator<Item = Result<Vec<T>>>,
+    {
+        if let Some(ref query) = self.query {
+            let mut all = Vec::new();
+            for values in pages {
+                for value in values? {
+                    all.push(serde_json::to_value(&value)?);
+                }
+            }
+            return self.print_result(query.apply(&json!({ "values": all }))?);
+        }
+
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        if self.quiet {
+            for values in pages {
+                write_ids(&mut out, &rows(&values?)?, T::id())?;
+            }
+            return Ok(());
+        }
+        let mut list = ListWriter::new(self, T::columns());
+        for values in pages {
+            let values = values?;
+            if self.fields.is_empty() {
+                list.write(&mut out, &values, rows(&values)?)?;
+            } else {
+                let items = self.select(&rows(&values)?)?;
+                let rows = items.iter().map(Selected::to_value).collect();
+                list.write(&mut out, &items, rows)?;
+            }
+        }
+        list.finish(&mut out)
+    }
+
+    /// Write raw bytes, such as downloaded package contents.
+    pub fn print_bytes(&self, bytes: &[u8]) -> Result<()> {
+        io::stdout().write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Print the result of a query, with one row per item of a list.
+    fn print_result(&self, result: Value) -> Result<()> {
+        match result {
+            Value::Array(items) => self.print_rows(items, true, &[]),
+            Value::Null => self.print_rows(Vec::new(), false, &[]),
+            result => self.print_rows(vec![result], false, &[]),
+        }
+    }
+
+    /// Select the fields from each row, then print the rows as a list or
+    /// as a single value.
+    fn print_rows(&self, rows: Vec<Value>, list: bool, default: &[&str]) -> Result<()> {
+        if self.fields.is_empty() {
+            return match rows.first() {
+                _ if list => self.write(&rows, &rows, &rows, default),
+                Some(row) => self.write(row, &rows, &rows, default),
+                None => self.write(&Value::Null, &rows, &rows, default),
+            };
+        }
+        let items = self.select(&rows)?;
+        let rows = items.iter().map(Selected::to_value).collect::<Vec<_>>();
+        match items.first() {
+            _ if list => self.write(&items, &items, &rows, default),
+            Some(item) => self.write(item, &items, &rows, default),
+            None => self.write(&Value::Null, &items, &rows, default),
+        }
+    }
+
+    /// Write the document as JSON or YAML, each line as NDJSON, or the rows
+    /// as CSV or a table.
+    fn write<D, L>(&self, doc: &D, lines: &[L], rows: &[Value], default: &[&str]) -> Result<()>
+    where
+        D: Serialize + ?Sized,
+        L: Serialize,
+    {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        match self.format {
+            Format::Json => writeln!(out, "{}", serde_json::to_string_pretty(doc)?)?,
+            Format::Yaml => writeln!(out, "{}", yaml(doc)?)?,
+            Format::Ndjson => {
+                for line in lines {
+                    writeln!(out, "{}", serde_json::to_string(line)?)?;
+                }
+            }
+            Format::Csv => {
+                let columns = self.columns(default, rows);
+                write_csv(&mut out, &columns, None)?;
+                for row in rows {
+                    write_csv(&mut out, &columns, Some(row))?;
+                }
+            }
+            Format::Table => write_table(&mut out, &self.columns(default, rows), rows)?,
+        }
+        Ok(())
+    }
+
+    /// Keep only the selected fields of each row. A field that is missing
+    /// from every row is reported as an error.
+    fn select(&self, rows: &[Value]) -> Result<Vec<Selected>> {
+        for field in &self.fields {
+            if !rows.is_empty() && rows.iter().all(|row| field.get(row).is_null()) {
+                let mut errors = rows.iter().map(|row| field.apply(row));
+                if let Some(Err(err)) = errors.next() {
+                    if errors.all(|result| result.is_err()) {
+                        Err(err)?
+                    }
+                }
+            }
+        }
+        Ok(rows
+            .iter()
+            .map(|row| Selected(self.fields.iter().map(|field| (field.to_string(), field.get(row))).collect()))
+            .collect())
+    }
+
+    /// Use the selected fields, the default columns, or the fields of the
+    /// first row.
+    fn columns(&self, default: &[&str], rows: &[Value]) -> Vec<String> {
+        if self.fields.is_empty() {
+            columns(default, rows)
+        } else {
+            self.fields.iter().map(Query::to_string).collect()
+        }
+    }
+}
+
+
+/// The selected fields of an item, serialized in the order they were given.
+struct Selected(Vec<(String, Value)>);
+
+impl Selected {
+    fn to_value(&self) -> Value { Value::Object(self.0.iter().cloned().collect()) }
+}
+
+impl Serialize for Selected {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (field, value) in &self.0 {
+            map.serialize_entry(field, value)?;
+        }
+        map.end()
+    }
+}
+
+
+/// Write a list of items as each page arrives.
+///
+/// JSON and YAML items are written under `values`, the same shape as a single
+/// page, unless fields are selected.
+struct ListWriter<'o> {
+    output:  &'o Output,
+    default: &'static [&'static str],
+    columns: Option<Vec<String>>,
+    count:   usize,
+    table:   Vec<Value>,
+    paged:   bool,
+}
+
+impl<'o> ListWriter<'o> {
+    fn new(output: &'o Output, default: &'static [&'static str]) -> Self {
+        ListWriter {
+            output,
+            default,
+            columns: None,
+            count: 0,
+            table: Vec::new(),
+            paged: output.fields.is_empty(),
+        }
+    }
+
+    fn write<S: Serialize>(&mut self, out: &mut impl Write, items: &[S], rows: Vec<Value>) -> Result<()> {
+        if self.columns.is_none() && !rows.is_empty() {
+            let columns = self.output.columns(self.default, &rows);
+            if self.output.format == Format::Csv {
+                write_csv(out, &columns, None)?;
+            }
+            self.columns = Some(columns);
+        }
+
+        match self.output.format {
+            Format::Json => {
+                let (open, indent) = if self.paged { ("{\n  \"values\": [", "\n    ") } else { ("[", "\n  ") };
+                for item in items {
+                    let json = serde_json::to_string_pretty(item)?;
+                    write!(out, "{}{}{}", if self.count == 0 { open } else { "," }, indent, json.replace('\n', indent))?;
+                    self.count += 1;
+                }
+            }
+            Format::Yaml => {
+                let (dash, indent) = if self.paged { ("  - ", "\n    ") } else { ("- ", "\n  ") };
+                for item in items {
+                    if self.count == 0 && self.paged {
+                        writeln!(out, "values:")?;
+                    }
+                    writeln!(out, "{}{}", dash, yaml(item)?.replace('\n', indent))?;
+                    self.count += 1;
+                }
+            }
+            Format::Ndjson => {
+                for item in items {
+                    writeln!(out, "{}", serde_json::to_string(item)?)?;
+                }
+            }
+            Format::Csv => {
+                let columns = self.columns.as_ref().expect("csv columns");
+                for row in &rows {
+                    write_csv(out, columns, Some(row))?;
+                }
+            }
+            Format::Table => self.table.extend(rows),
+        }
+        Ok(())
+    }
+
+    fn finish(self, out: &mut impl Write) -> Result<()> {
+        match self.output.format {
+            Format::Json if self.paged => writeln!(out, "{}", if self.count == 0 { "{\n  \"values\": []\n}" } else { "\n  ]\n}" })?,
+            Format::Json => writeln!(out, "{}", if self.count == 0 { "[]" } else { "\n]" })?,
+            Format::Yaml if self.count == 0 => writeln!(out, "{}", if self.paged { "values: []" } else { "[]" })?,
+            Format::Yaml | Format::Ndjson => (),
+            Format::Csv => {
+                if self.columns.is_none() && (!self.default.is_empty() || !self.output.fields.is_empty()) {
+                    write_csv(out, &self.output.columns(self.default, &[]), None)?;
+                }
+            }
+            Format::Table => {
+                let columns = match self.columns {
+                    Some(columns) => columns,
+                    None => self.output.columns(self.default, &[]),
+                };
+                write_table(out, &columns, &self.table)?
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Serialize to YAML without the leading document marker.
+fn yaml<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let text = serde_yaml::to_string(value)?;
+    let text = text.trim_end();
+    Ok(match text.find("---") {
+        Some(0) => text[3..].trim_start_matches(&[' ', '\n'][..]).into(),
+        _ => text.into(),
+    })
+}
+
+/// Use the default columns, or the fields of the first row.
+fn columns(default: &[&str], rows: &[Value]) -> Vec<String> {
+    if !default.is_empty() {
+        default.iter().map(|column| column.to_string()).collect()
+    } else if let Some(Value::Object(fields)) = rows.first() {
+        fields.keys().cloned().collect()
+    } else {
+        vec!["value".into()]
+    }
+}
+
+/// Format a field as plain text. Lists are joined with commas.
+fn cell(row: &Value, column: &str) -> String {
+    fn text(value: &Value) -> String {
+        match value {
+            Value::Null => String::new(),
+            Value::String(val) => val.clone(),
+            Value::Array(vals) => vals.iter().map(text).collect::<Vec<_>>().join(","),
+            val => val.to_string(),
+        }
+    }
+    match row {
+        Value::Object(fields) => fields.get(column).map(text).unwrap_or_default(),
+        val => text(val),
+    }
+}
+
+/// Write the ID field of each row on its own line.
+fn write_ids(out: &mut impl Write, rows: &[Value], id: &str) -> Result<()> {
+    for row in rows {
+        let id = cell(row, id);
+        if !id.is_empty() {
+            writeln!(out, "{}", id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the header, or the row's fields, as one RFC 4180 CSV line.
+fn write_csv(out: &mut impl Write, columns: &[String], row: Option<&Value>) -> Result<()> {
+    let fields = columns
+        .iter()
+        .map(|column| {
+            let field = match row {
+                Some(row) => cell(row, column),
+                None => column.clone(),
+            };
+            if field.contains(&[',', '"', '\n', '\r'][..]) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", fields.join(","))?;
+    Ok(())
+}
+
+/// Write the rows as columns aligned with spaces.
+fn write_table(out: &mut impl Write, columns: &[String], rows: &[Value]) -> Result<()> {
+    let cells = rows
+        .iter()
+        .map(|row| columns.iter().map(|column| cell(row, column).replace('\n', " ")).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| cells.iter().map(|row| row[i].chars().count()).fold(column.chars().count(), usize::max))
+        .collect::<Vec<_>>();
+
+    let line = |fields: &[String]| {
+        let padded = fields
+            .iter()
+            .zip(&widths)
+            .map(|(field, width)| format!("{}{}", field, " ".repeat(width - field.chars().count())))
+            .collect::<Vec<_>>();
+        padded.join("  ").trim_end().to_string()
+    };
+    writeln!(out, "{}", line(columns))?;
+    for row in &cells {
+        writeln!(out, "{}", line(row))?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn devices() -> Vec<Value> {
+        vec![
+            json!({"uuid": "d1", "deviceName": "bench, \"one\"", "groups": ["a", "b"]}),
+            json!({"uuid": "d2", "deviceName": "two", "lastSeen": null}),
+        ]
+    }
+
+    #[test]
+    fn write_csv_rows() {
+        let columns = vec!["uuid".into(), "deviceName".into(), "groups".into(), "lastSeen".into()];
+        let mut out = Vec::new();
+        write_csv(&mut out, &columns, None).expect("header");
+        for row in &devices() {
+            write_csv(&mut out, &columns, Some(row)).expect("row");
+        }
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "uuid,deviceName,groups,lastSeen\nd1,\"bench, \"\"one\"\"\",\"a,b\",\nd2,two,,\n"
+        );
+    }
+
+    #[test]
+    fn write_one_id_per_line() {
+        let mut rows = devices();
+        rows.push(json!({ "deviceName": "three" }));
+        let mut out = Vec::new();
+        write_ids(&mut out, &rows, "uuid").expect("ids");
+        assert_eq!(String::from_utf8(out).expect("utf8"), "d1\nd2\n");
+    }
+
+    #[test]
+    fn write_aligned_table() {
+        let rows = devices();
+        let mut out = Vec::new();
+        write_table(&mut out, &columns(&[], &rows), &rows).expect("table");
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "deviceName    groups  uuid\nbench, \"one\"  a,b     d1\ntwo                   d2\n"
+        );
+    }
+
+    #[test]
+    fn yaml_without_marker() {
+        assert_eq!(yaml(&json!({"a": 1, "b": ["x"]})).expect("yaml"), "a: 1\nb:\n  - x");
+        assert_eq!(yaml(&"id").expect("yaml"), "id");
+    }
+
+    #[test]
+    fn stream_pages_like_a_single_page() {
+        let pages = vec![vec![json!({"uuid": "d1", "groups": ["a"]})], vec![json!({"uuid": "d2"})]];
+        let single = json!({"values": [{"uuid": "d1", "groups": ["a"]}, {"uuid": "d2"}]});
+        for format in &[Format::Json, Format::Yaml] {
+            let output = Output { format: *format, ..Output::default() };
+            for pages in &[pages.clone(), Vec::new()] {
+                let mut out = Vec::new();
+                let mut list = ListWriter::new(&output, &[]);
+                for page in pages {
+                    list.write(&mut out, page, page.clone()).expect("page");
+                }
+                list.finish(&mut out).expect("finish");
+                let parsed: Value = serde_yaml::from_slice(&out).expect("parse");
+                let expected = if pages.is_empty() { json!({"values": []}) } else { single.clone() };
+                assert_eq!(parsed, expected, "{:?}", format);
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use openssl::{
+    base64,
+    hash::{hash, MessageDigest},
+    pkcs12::Pkcs12,
+    ssl::{SslConnector, SslMethod, SslVerifyMode},
+    x509::{X509Ref, X509},
+};
+use reqwest::{StatusCode, Url};
+use serde_json;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use api::auth_plus::ClientTls;
+use error::{Error, Result};
+use http::HttpSettings;
+use transport::{find_header, Body, Request, Response, Transport};
+
+
+/// Send requests to pinned services over connections whose server key is
+/// checked against the pin during the TLS handshake.
+///
+/// The reqwest client offers no hook into its handshake, so each request to
+/// a pinned address is written over a new connection of its own. Requests to
+/// any other address go to `inner`.
+pub struct PinnedTransport {
+    inner:    Arc<dyn Transport>,
+    settings: HttpSettings,
+    tls:      Option<ClientTls>,
+    /// Server addresses mapped to the service name and pin to check.
+    pins:     BTreeMap<String, (String, String)>,
+}
+
+impl PinnedTransport {
+    pub fn new(
+        inner: Arc<dyn Transport>,
+        settings: HttpSettings,
+        tls: Option<ClientTls>,
+        pins: BTreeMap<String, (String, String)>,
+    ) -> Self {
+        PinnedTransport {
+            inner,
+            settings,
+            tls,
+            pins,
+        }
+    }
+
+    /// Open a TLS connection that fails the handshake unless the server key
+    /// matches the pin.
+    fn connect(&self, host: &str, port: u16, service: &str, pin: &str) -> Result<impl Read + Write> {
+        let timeout = Duration::from_secs(self.settings.connect_timeout_secs);
+        let stream = match self.settings.proxy {
+            Some(ref proxy) => proxy_tunnel(&Url::parse(proxy)?, host, port, timeout)?,
+            None => connect_tcp(host, port, timeout)?,
+        };
+        stream.set_read_timeout(Some(Duration::from_secs(self.settings.read_timeout_secs)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(self.settings.read_timeout_secs)))?;
+
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+        for bundle in &self.settings.ca_bundles {
+            for cert in X509::stack_from_pem(&fs::read(bundle)?)? {
+                connector.cert_store_mut().add_cert(cert)?;
+            }
+        }
+        if let Some(ref tls) = self.tls {
+            let identity = Pkcs12::from_der(&tls.pkcs12)?.parse2("")?;
+            if let (Some(cert), Some(pkey)) = (identity.cert, identity.pkey) {
+                connector.set_certificate(&cert)?;
+                connector.set_private_key(&pkey)?;
+            }
+            if let Some(ref server_ca) = tls.server_ca {
+                connector.cert_store_mut().add_cert(X509::from_pem(server_ca)?)?;
+            }
+        }
+
+        let mismatch = Arc::new(Mutex::new(None));
+        let (expected, found) = (pin.to_string(), mismatch.clone());
+        connector.set_verify_callback(SslVerifyMode::PEER, move |verified, ctx| {
+            if !verified || ctx.error_depth() != 0 {
+                return verified;
+            }
+            match ctx.current_cert().map(key_pin) {
+                Some(Ok(ref key)) if *key == expected => true,
+                Some(Ok(key)) => {
+                    *found.lock().expect("pin lock") = Some(key);
+                    false
+                }
+                _ => false,
+            }
+        });
+
+        connector.build().connect(host, stream).map_err(|err| {
+            let address = format!("{}:{}", host, port);
+            match mismatch.lock().expect("pin lock").take() {
+                Some(key) => Error::Pin(format!("{} ({}) presented key {} but {} is pinned", service, address, key, pin)),
+                None => Error::Pin(format!("tls handshake with {} ({}) failed: {}", service, address, err)),
+            }
+        })
+    }
+}
+
+impl Transport for PinnedTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        let host = req.url.host_str().unwrap_or_default();
+        let port = req.url.port_or_known_default().unwrap_or(443);
+        let (service, pin) = match self.pins.get(&format!("{}:{}", host, port)) {
+            Some(pinned) if req.url.scheme() == "https" => pinned,
+            _ => return self.inner.execute(req),
+        };
+        debug!("sending request to {} over a connection pinned to {}", service, pin);
+        let mut stream = self.connect(host, port, service, pin)?;
+        stream.write_all(&encode_request(req)?)?;
+        stream.flush()?;
+        read_response(BufReader::new(stream))
+    }
+}
+
+
+/// Return the `sha256/<base64>` pin of the certificate's public key.
+pub fn key_pin(cert: &X509Ref) -> Result<String> {
+    let digest = hash(MessageDigest::sha256(), &cert.public_key()?.public_key_to_der()?)?;
+    Ok(format!("sha256/{}", base64::encode_block(&digest)))
+}
+
+/// Connect to the first address of the host that accepts within the timeout.
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host));
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last = err,
+        }
+    }
+    Err(last)?
+}
+
+/// Open a tunnel to `host:port` through an HTTP proxy.
+fn proxy_tunnel(proxy: &Url, host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let proxy_host = proxy.host_str().unwrap_or_default();
+    let proxy_port = proxy.port_or_known_default().unwrap_or(8080);
+    let mut stream = connect_tcp(proxy_host, proxy_port, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    write!(stream, "CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n\r\n", host, port)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    let _ = reader.read_line(&mut status)?;
+    if status.split_whitespace().nth(1) != Some("200") {
+        Err(Error::Pin(format!("proxy refused tunnel to {}:{}: {}", host, port, status.trim())))?
+    }
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    Ok(reader.into_inner())
+}
+
+/// Write the request as HTTP/1.1, closing the connection after the response.
+fn encode_request(req: &Request) -> Result<Vec<u8>> {
+    let (content_type, body) = match req.body {
+        Some(Body::Json(ref json)) => (Some("application/json".to_string()), serde_json::to_vec(json)?),
+        Some(Body::Form(ref form)) => (Some("application/x-www-form-urlencoded".to_string()), form.clone().into_bytes()),
+        Some(Body::Multipart(ref parts)) => {
+            let boundary = Uuid::new_v4().to_simple().to_string();
+            let mut body = Vec::new();
+            for part in parts {
+                let file_name = part.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                write!(body, "--{}\r\ncontent-disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n", boundary, part.name, file_name)?;
+                write!(body, "content-type: application/octet-stream\r\n\r\n")?;
+                body.extend_from_slice(&fs::read(&part.path)?);
+                body.extend_from_slice(b"\r\n");
+            }
+            write!(body, "--{}--\r\n", boundary)?;
+            (Some(format!("multipart/form-data; boundary={}", boundary)), body)
+        }
+        None => (None, Vec::new()),
+    };
+
+    let host = match req.url.port() {
+        Some(port) => format!("{}:{}", req.url.host_str().unwrap_or_default(), port),
+        None => req.url.host_str().unwrap_or_default().to_string(),
+    };
+    let target = match req.url.query() {
+        Some(query) => format!("{}?{}", req.url.path(), query),
+        None => req.url.path().to_string(),
+    };
+
+    let mut out = Vec::new();
+    write!(out, "{} {} HTTP/1.1\r\nhost: {}\r\n", req.method, target, host)?;
+    for (name, value) in &req.headers {
+        write!(out, "{}: {}\r\n", name, value)?;
+    }
+    if let Some(content_type) = content_type {
+        write!(out, "content-type: {}\r\n", content_type)?;
+    }
+    if req.body.is_some() {
+        write!(out, "content-length: {}\r\n", body.len())?;
+    }
+    write!(out, "accept: */*\r\nconnection: close\r\n\r\n")?;
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Read the status, headers and body of an HTTP/1.1 response.
+fn read_response(mut reader: impl BufRead) -> Result<Response> {
+    let mut line = String::new();
+    let _ = reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| Error::Parse(format!("HTTP status line: {}", line.trim())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            headers.push((line[..colon].trim().to_lowercase(), line[colon + 1..].trim().to_string()));
+        }
+    }
+
+    let mut body = Vec::new();
+    if find_header(&headers, "transfer-encoding").is_some_and(|val| val.eq_ignore_ascii_case("chunked")) {
+        loop {
+            line.clear();
+            let _ = reader.read_line(&mut line)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| Error::Parse(format!("HTTP chunk size: {}", size)))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            let _ = reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = find_header(&headers, "content-length").and_then(|val| val.parse().ok()) {
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        match reader.read_to_end(&mut body) {
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+            other => {
+                let _ = other?;
+            }
+        }
+    }
+    Ok(Response { status, headers, body })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+
+    #[test]
+    fn encode_json_request() {
+        let req = Request::new(Method::POST, "https://registry:8443/api/v1/devices?limit=1")
+            .expect("request")
+            .bearer_auth("token")
+            .json(&json!({"a": 1}))
+            .expect("json");
+        assert_eq!(
+            String::from_utf8(encode_request(&req).expect("encode")).expect("utf8"),
+            "POST /api/v1/devices?limit=1 HTTP/1.1\r\nhost: registry:8443\r\nauthorization: Bearer token\r\n\
+             content-type: application/json\r\ncontent-length: 7\r\naccept: */*\r\nconnection: close\r\n\r\n{\"a\":1}"
+        );
+    }
+
+    #[test]
+    fn read_chunked_and_sized_responses() {
+        let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nX-Request-Id: abc\r\n\r\n3\r\nfoo\r\n4;ext\r\nbar!\r\n0\r\n\r\n";
+        let resp = read_response(chunked.as_bytes()).expect("chunked");
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.header("x-request-id"), Some("abc"));
+        assert_eq!(resp.text(), "foobar!");
+
+        let sized = "HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\nnoextra";
+        let resp = read_response(sized.as_bytes()).expect("sized");
+        assert_eq!(resp.status, StatusCode::NOT_FOUND);
+        assert_eq!(resp.text(), "no");
+    }
+}
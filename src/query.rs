@@ -0,0 +1,511 @@
+use serde_json::{self, Value};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    iter::Peekable,
+    str::{CharIndices, FromStr},
+};
+
+use error::{Error, Result};
+
+
+/// A parsed query expression, using a small subset of JMESPath.
+///
+/// Supported are field paths (`a.b`), indexes (`a[0]`, `a[-1]`), projections
+/// over lists (`a[*].b`) and objects (`a.*.b`), flattening (`a[].b`), filters
+/// (`a[?b==`1`]`), comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`), `&&`, `||`,
+/// `!` and parentheses. Literals are JSON in backticks or raw strings in single
+/// quotes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    text: String,
+    expr: Expr,
+}
+
+impl Query {
+    /// Evaluate the query against a value.
+    pub fn apply(&self, value: &Value) -> Result<Value> {
+        self.expr.eval(value, true).map_err(|err| Error::Query(format!("{} in `{}`", err, self.text)))
+    }
+
+    /// Evaluate the query, returning null when a field is missing.
+    pub fn get(&self, value: &Value) -> Value { self.expr.eval(value, false).unwrap_or(Value::Null) }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            text:  s,
+            chars: s.char_indices().peekable(),
+        };
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        if let Some(&(pos, c)) = parser.chars.peek() {
+            Err(parser.error(pos, &format!("unexpected `{}`", c)))?
+        }
+        Ok(Query { text: s.into(), expr })
+    }
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "{}", self.text) }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Path(Vec<Step>),
+    Literal(Value),
+    Compare(Box<Expr>, Op, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Step {
+    Field(String),
+    Index(i64),
+    Wildcard,
+    Flatten,
+    Filter(Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Expr {
+    /// Evaluate against the current value. When `strict`, a missing field or
+    /// a step that doesn't fit the value is an error rather than null.
+    fn eval(&self, value: &Value, strict: bool) -> ::std::result::Result<Value, String> {
+        match self {
+            Expr::Path(steps) => eval_steps(value, steps, strict),
+            Expr::Literal(literal) => Ok(literal.clone()),
+            Expr::Compare(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(value, strict)?, rhs.eval(value, strict)?);
+                Ok(Value::Bool(compare(&lhs, *op, &rhs)))
+            }
+            Expr::And(lhs, rhs) => {
+                let lhs = lhs.eval(value, strict)?;
+                if truthy(&lhs) { rhs.eval(value, strict) } else { Ok(lhs) }
+            }
+            Expr::Or(lhs, rhs) => {
+                let lhs = lhs.eval(value, strict)?;
+                if truthy(&lhs) { Ok(lhs) } else { rhs.eval(value, strict) }
+            }
+            Expr::Not(expr) => Ok(Value::Bool(!truthy(&expr.eval(value, strict)?))),
+        }
+    }
+}
+
+fn eval_steps(value: &Value, steps: &[Step], strict: bool) -> ::std::result::Result<Value, String> {
+    let (step, rest) = match steps.split_first() {
+        Some(next) => next,
+        None => return Ok(value.clone()),
+    };
+
+    match (step, value) {
+        (Step::Field(name), Value::Object(fields)) => match fields.get(name) {
+            Some(field) => eval_steps(field, rest, strict),
+            None if strict => Err(format!(
+                "unknown field `{}` (available: {})",
+                name,
+                fields.keys().map(String::as_str).collect::<Vec<_>>().join(", ")
+            )),
+            None => Ok(Value::Null),
+        },
+        (Step::Field(name), Value::Array(_)) if strict => {
+            Err(format!("`{}` is read from a list; use `[*].{}` to read it from each item", name, name))
+        }
+        (Step::Index(index), Value::Array(items)) => {
+            let index = if *index < 0 { items.len() as i64 + index } else { *index };
+            match items.get(index as usize) {
+                Some(item) if index >= 0 => eval_steps(item, rest, strict),
+                _ => Ok(Value::Null),
+            }
+        }
+        (Step::Wildcard, Value::Array(items)) => project(&items.iter().collect::<Vec<_>>(), rest, strict),
+        (Step::Wildcard, Value::Object(fields)) => project(&fields.values().collect::<Vec<_>>(), rest, strict),
+        (Step::Flatten, Value::Array(items)) => {
+            let mut flat = Vec::new();
+            for item in items {
+                match item {
+                    Value::Array(inner) => flat.extend(inner.iter().cloned()),
+                    item => flat.push(item.clone()),
+                }
+            }
+            project(&flat.iter().collect::<Vec<_>>(), rest, strict)
+        }
+        (Step::Filter(predicate), Value::Array(items)) => {
+            let mut matched = Vec::new();
+            for item in items {
+                if truthy(&predicate.eval(item, false)?) {
+                    matched.push(item);
+                }
+            }
+            if matched.is_empty() && strict {
+                check_any(items.iter(), |item| predicate.eval(item, true))?;
+            }
+            project(&matched, rest, strict)
+        }
+        (_, Value::Null) => Ok(Value::Null),
+        (step, value) if strict => Err(format!("{} cannot be applied to {}", step, kind(value))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Apply the steps up to the next flatten to each item, dropping nulls, then
+/// apply the rest to the list of results. When nothing is left, report the
+/// error from the strict evaluation if no item allows it.
+fn project(items: &[&Value], rest: &[Step], strict: bool) -> ::std::result::Result<Value, String> {
+    let split = rest.iter().position(|step| *step == Step::Flatten).unwrap_or(rest.len());
+    let (each, rest) = rest.split_at(split);
+    let mut values = Vec::new();
+    for item in items {
+        match eval_steps(item, each, false)? {
+            Value::Null => (),
+            value => values.push(value),
+        }
+    }
+    if values.is_empty() && strict {
+        check_any(items.iter().cloned(), |item| eval_steps(item, each, true))?;
+    }
+    eval_steps(&Value::Array(values), rest, strict)
+}
+
+/// Succeed if the evaluation succeeds for any item, or there are no items.
+fn check_any<'v, F>(mut items: impl Iterator<Item = &'v Value>, eval: F) -> ::std::result::Result<(), String>
+where
+    F: Fn(&Value) -> ::std::result::Result<Value, String>,
+{
+    let first = match items.next() {
+        Some(item) => eval(item),
+        None => return Ok(()),
+    };
+    match first {
+        Ok(_) => Ok(()),
+        Err(err) => if items.any(|item| eval(item).is_ok()) { Ok(()) } else { Err(err) },
+    }
+}
+
+fn compare(lhs: &Value, op: Op, rhs: &Value) -> bool {
+    let order = match (lhs, rhs) {
+        (Value::Number(lhs), Value::Number(rhs)) => lhs.as_f64().and_then(|lhs| rhs.as_f64().and_then(|rhs| lhs.partial_cmp(&rhs))),
+        (Value::String(lhs), Value::String(rhs)) => Some(lhs.cmp(rhs)),
+        _ => None,
+    };
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    match op {
+        Op::Eq => lhs == rhs || order == Some(Ordering::Equal),
+        Op::Ne => lhs != rhs && order != Some(Ordering::Equal),
+        Op::Lt => order == Some(Ordering::Less),
+        Op::Le => order == Some(Ordering::Less) || order == Some(Ordering::Equal),
+        Op::Gt => order == Some(Ordering::Greater),
+        Op::Ge => order == Some(Ordering::Greater) || order == Some(Ordering::Equal),
+    }
+}
+
+/// False, null, empty strings, lists and objects are false.
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => false,
+        Value::String(val) => !val.is_empty(),
+        Value::Array(vals) => !vals.is_empty(),
+        Value::Object(vals) => !vals.is_empty(),
+        _ => true,
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    match value {
+        Value::Null      => "null",
+        Value::Bool(_)   => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_)  => "a list",
+        Value::Object(_) => "an object",
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Step::Field(name) => write!(f, "`.{}`", name),
+            Step::Index(index) => write!(f, "`[{}]`", index),
+            Step::Wildcard => write!(f, "`[*]`"),
+            Step::Flatten => write!(f, "`[]`"),
+            Step::Filter(_) => write!(f, "`[?...]`"),
+        }
+    }
+}
+
+
+/// A recursive descent parser for query expressions.
+struct Parser<'t> {
+    text:  &'t str,
+    chars: Peekable<CharIndices<'t>>,
+}
+
+impl<'t> Parser<'t> {
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut lhs = self.comparison()?;
+        while self.eat("&&") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let lhs = self.unary()?;
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let op = if self.eat("==") { Op::Eq }
+            else if self.eat("!=") { Op::Ne }
+            else if self.eat("<=") { Op::Le }
+            else if self.eat(">=") { Op::Ge }
+            else if self.eat("<")  { Op::Lt }
+            else if self.eat(">")  { Op::Gt }
+            else { return Ok(lhs) };
+        Ok(Expr::Compare(Box::new(lhs), op, Box::new(self.unary()?)))
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        match self.chars.peek().cloned() {
+            Some((_, '!')) => {
+                let _ = self.chars.next();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some((_, '(')) => {
+                let _ = self.chars.next();
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some((pos, '`')) => {
+                let _ = self.chars.next();
+                let text = self.until('`', pos)?;
+                // Bare words such as `Error` are read as strings.
+                Ok(Expr::Literal(serde_json::from_str(&text).unwrap_or(Value::String(text))))
+            }
+            Some((pos, '\'')) => {
+                let _ = self.chars.next();
+                Ok(Expr::Literal(Value::String(self.until('\'', pos)?)))
+            }
+            _ => self.path(),
+        }
+    }
+
+    fn path(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        let mut steps = Vec::new();
+        match self.chars.peek().cloned() {
+            Some((_, '@')) => {
+                let _ = self.chars.next();
+            }
+            Some((_, '[')) => (),
+            Some((pos, _)) => steps.push(Step::Field(self.field(pos)?)),
+            None => Err(self.error(self.text.len(), "expected a field"))?,
+        }
+        loop {
+            match self.chars.peek().cloned() {
+                Some((pos, '.')) => {
+                    let _ = self.chars.next();
+                    if let Some(&(_, '*')) = self.chars.peek() {
+                        let _ = self.chars.next();
+                        steps.push(Step::Wildcard);
+                    } else {
+                        steps.push(Step::Field(self.field(pos + 1)?));
+                    }
+                }
+                Some((pos, '[')) => {
+                    let _ = self.chars.next();
+                    steps.push(self.bracket(pos)?);
+                }
+                _ => break,
+            }
+        }
+        Ok(Expr::Path(steps))
+    }
+
+    fn bracket(&mut self, start: usize) -> Result<Step> {
+        self.skip_whitespace();
+        let step = match self.chars.peek().cloned() {
+            Some((_, ']')) => Step::Flatten,
+            Some((_, '*')) => {
+                let _ = self.chars.next();
+                Step::Wildcard
+            }
+            Some((_, '?')) => {
+                let _ = self.chars.next();
+                Step::Filter(Box::new(self.expr()?))
+            }
+            Some((pos, c)) if c == '-' || c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(&(_, c)) = self.chars.peek() {
+                    if c == '-' || c.is_ascii_digit() {
+                        digits.push(c);
+                        let _ = self.chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Step::Index(digits.parse().map_err(|_| self.error(pos, "invalid index"))?)
+            }
+            _ => Err(self.error(start, "expected `*`, `?`, an index or `]` after `[`"))?,
+        };
+        self.expect(']')?;
+        Ok(step)
+    }
+
+    /// Parse a field name, or a double-quoted name with any characters.
+    fn field(&mut self, pos: usize) -> Result<String> {
+        if let Some(&(start, '"')) = self.chars.peek() {
+            let _ = self.chars.next();
+            return self.until('"', start);
+        }
+        let mut name = String::new();
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                name.push(c);
+                let _ = self.chars.next();
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            Err(self.error(pos, "expected a field name"))?
+        }
+        Ok(name)
+    }
+
+    fn until(&mut self, end: char, start: usize) -> Result<String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) if c == end => text.push(c),
+                    Some((_, c)) => {
+                        text.push('\\');
+                        text.push(c);
+                    }
+                    None => break,
+                },
+                Some((_, c)) if c == end => return Ok(text),
+                Some((_, c)) => text.push(c),
+                None => break,
+            }
+        }
+        Err(self.error(start, &format!("missing closing {}", end)))
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let pos = match self.chars.peek() {
+            Some(&(pos, _)) => pos,
+            None => return false,
+        };
+        if self.text[pos..].starts_with(token) {
+            for _ in token.chars() {
+                let _ = self.chars.next();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some((_, next)) if next == c => Ok(()),
+            Some((pos, next)) => Err(self.error(pos, &format!("expected `{}` but found `{}`", c, next))),
+            None => Err(self.error(self.text.len(), &format!("expected `{}`", c))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            let _ = self.chars.next();
+        }
+    }
+
+    fn error(&self, pos: usize, msg: &str) -> Error {
+        Error::Query(format!("{} at position {} in `{}`", msg, pos + 1, self.text))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn page() -> Value {
+        json!({
+            "values": [
+                {"uuid": "d1", "deviceStatus": "Error", "hardware": ["a", "b"], "seen": 3},
+                {"uuid": "d2", "deviceStatus": "UpToDate", "hardware": ["c"]},
+                {"uuid": "d3", "deviceStatus": "Error", "hardware": [], "seen": 9},
+            ],
+            "total": 3,
+        })
+    }
+
+    fn query(text: &str) -> Result<Value> { text.parse::<Query>()?.apply(&page()) }
+
+    #[test]
+    fn paths_and_projections() {
+        assert_eq!(query("total").expect("total"), json!(3));
+        assert_eq!(query("values[0].uuid").expect("index"), json!("d1"));
+        assert_eq!(query("values[-1].uuid").expect("last"), json!("d3"));
+        assert_eq!(query("values[*].uuid").expect("wildcard"), json!(["d1", "d2", "d3"]));
+        assert_eq!(query("values[*].seen").expect("missing"), json!([3, 9]));
+        assert_eq!(query("values[*].hardware[]").expect("flatten"), json!(["a", "b", "c"]));
+        assert_eq!(query("values[0].*").expect("object").as_array().map(Vec::len), Some(4));
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(query("values[?deviceStatus==`Error`].uuid").expect("filter"), json!(["d1", "d3"]));
+        assert_eq!(query("values[?deviceStatus=='Error' && seen > `5`].uuid").expect("and"), json!(["d3"]));
+        assert_eq!(query("values[?!(seen) || uuid == `\"d1\"`].uuid").expect("or"), json!(["d1", "d2"]));
+        assert_eq!(query("values[?deviceStatus==`Outdated`]").expect("none"), json!([]));
+    }
+
+    #[test]
+    fn bad_paths() {
+        let err = format!("{}", query("valeus[*].uuid").unwrap_err());
+        assert!(err.contains("unknown field `valeus` (available: total, values)"), "{}", err);
+        let err = format!("{}", query("values.uuid").unwrap_err());
+        assert!(err.contains("use `[*].uuid`"), "{}", err);
+        let err = format!("{}", query("values[*].uid").unwrap_err());
+        assert!(err.contains("unknown field `uid`"), "{}", err);
+        let err = format!("{}", query("values[?status==`Error`]").unwrap_err());
+        assert!(err.contains("unknown field `status`"), "{}", err);
+        let err = format!("{}", query("values[?uuid==`d1`").unwrap_err());
+        assert!(err.contains("expected `]` at position 19"), "{}", err);
+        let err = format!("{}", query("values[x]").unwrap_err());
+        assert!(err.contains("after `[` at position 7"), "{}", err);
+    }
+}
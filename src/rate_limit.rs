@@ -0,0 +1,139 @@
+use reqwest::Url;
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use error::Result;
+use http::millis;
+use transport::{Request, Response, Transport};
+
+
+/// The sustained request rate and burst size allowed for a service.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst:      u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            per_second: 10.0,
+            burst:      10,
+        }
+    }
+}
+
+
+/// A token bucket that refills at a steady rate up to the burst size.
+///
+/// Each request reserves a token, waiting for the bucket to refill when it is
+/// empty, so concurrent requests are spaced out in the order they arrive.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate:   f64,
+    burst:  f64,
+    tokens: f64,
+    last:   Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: &RateLimit, now: Instant) -> Self {
+        let burst = f64::from(limit.burst.max(1));
+        TokenBucket {
+            rate: limit.per_second.max(0.001),
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    /// Reserve a token, returning how long to wait before using it.
+    pub fn reserve(&mut self, now: Instant) -> Duration {
+        if now > self.last {
+            let elapsed = now.duration_since(self.last);
+            let refill = elapsed.as_secs() as f64 * self.rate + f64::from(elapsed.subsec_nanos()) * self.rate / 1e9;
+            self.tokens = (self.tokens + refill).min(self.burst);
+            self.last = now;
+        }
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_nanos((-self.tokens / self.rate * 1e9) as u64)
+        }
+    }
+}
+
+
+/// Delay each request until the rate limit for its service allows it.
+pub struct RateLimitedTransport {
+    inner:   Arc<dyn Transport>,
+    /// Service base URLs with the service name and bucket to draw from.
+    buckets: Vec<(Url, String, Mutex<TokenBucket>)>,
+}
+
+impl RateLimitedTransport {
+    pub fn new(inner: Arc<dyn Transport>, limits: Vec<(Url, String, RateLimit)>) -> Self {
+        let now = Instant::now();
+        RateLimitedTransport {
+            inner,
+            buckets: limits
+                .into_iter()
+                .map(|(url, service, limit)| (url, service, Mutex::new(TokenBucket::new(&limit, now))))
+                .collect(),
+        }
+    }
+
+    /// Wait for a token from the most specific service matching the URL.
+    fn wait(&self, url: &Url) {
+        let bucket = self
+            .buckets
+            .iter()
+            .filter(|(base, _, _)| url.as_str().starts_with(base.as_str()))
+            .max_by_key(|(base, _, _)| base.as_str().len());
+        if let Some((_, service, bucket)) = bucket {
+            let delay = bucket.lock().expect("rate limit lock").reserve(Instant::now());
+            if delay > Duration::from_secs(0) {
+                debug!("rate limit for {}: waiting {}ms", service, millis(delay));
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+impl Transport for RateLimitedTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        self.wait(&req.url);
+        self.inner.execute(req)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn bucket_refills_at_rate() {
+        let start = Instant::now();
+        let limit = RateLimit { per_second: 10.0, burst: 2 };
+        let mut bucket = TokenBucket::new(&limit, start);
+
+        assert_eq!(millis(bucket.reserve(start)), 0);
+        assert_eq!(millis(bucket.reserve(start)), 0);
+        assert_eq!(millis(bucket.reserve(start)), 100);
+        assert_eq!(millis(bucket.reserve(start)), 200);
+
+        let later = start + Duration::from_millis(300);
+        assert_eq!(millis(bucket.reserve(later)), 0);
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(millis(bucket.reserve(much_later)), 0);
+        assert_eq!(millis(bucket.reserve(much_later)), 0);
+        assert_eq!(millis(bucket.reserve(much_later)), 100);
+    }
+}
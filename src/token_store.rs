@@ -0,0 +1,130 @@
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    pbkdf2::{self, PBKDF2_HMAC_SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde_json;
+use std::{env, fs, io::ErrorKind, num::NonZeroU32, path::PathBuf};
+
+use api::auth_plus::AccessToken;
+use config::write_private_file;
+use error::{Error, Result};
+
+
+const PASSPHRASE_ENV: &str = "OTA_TOKEN_PASSPHRASE";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// An encrypted file for storing the access token outside the config file.
+///
+/// The token is sealed with a key derived from the contents of `key_file`, or
+/// else from the passphrase in the `OTA_TOKEN_PASSPHRASE` environment variable.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenStore {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+}
+
+impl TokenStore {
+    /// Encrypt then save the access token.
+    pub fn seal(&self, token: &AccessToken) -> Result<()> {
+        debug!("sealing access token to {:?}", self.path);
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt).map_err(|_| Error::Crypto("generating salt".into()))?;
+        rng.fill(&mut nonce).map_err(|_| Error::Crypto("generating nonce".into()))?;
+
+        let mut sealed = serde_json::to_vec(token)?;
+        self.key(&salt)?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .map_err(|_| Error::Crypto("sealing access token".into()))?;
+
+        let mut contents = Vec::with_capacity(SALT_LEN + NONCE_LEN + sealed.len());
+        contents.extend_from_slice(&salt);
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&sealed);
+        write_private_file(&self.path, &contents)
+    }
+
+    /// Read then decrypt a previously saved access token.
+    pub fn open(&self) -> Result<Option<AccessToken>> {
+        let mut contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => Err(err)?,
+        };
+        debug!("opening access token from {:?}", self.path);
+        if contents.len() < SALT_LEN + NONCE_LEN {
+            Err(Error::Crypto(format!("token store {:?} is truncated", self.path)))?
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        salt.copy_from_slice(&contents[..SALT_LEN]);
+        nonce.copy_from_slice(&contents[SALT_LEN..SALT_LEN + NONCE_LEN]);
+        let sealed = &mut contents[SALT_LEN + NONCE_LEN..];
+        let token = self
+            .key(&salt)?
+            .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::empty(), sealed)
+            .map_err(|_| Error::Crypto("unable to decrypt token store (wrong passphrase or key file?)".into()))?;
+        Ok(Some(serde_json::from_slice(token)?))
+    }
+
+    /// Derive the encryption key from the key file or passphrase.
+    fn key(&self, salt: &[u8]) -> Result<LessSafeKey> {
+        let secret = match self.key_file {
+            Some(ref key_file) => fs::read(key_file)?,
+            None => env::var(PASSPHRASE_ENV).map(String::into_bytes).map_err(|_| {
+                Error::Config(format!("the token store requires a `key_file` or the {} environment variable", PASSPHRASE_ENV))
+            })?,
+        };
+
+        let mut key = [0u8; KEY_LEN];
+        let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).expect("non-zero iterations");
+        pbkdf2::derive(PBKDF2_HMAC_SHA256, iterations, salt, &secret, &mut key);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key).map_err(|_| Error::Crypto("creating key".into()))?;
+        Ok(LessSafeKey::new(key))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+
+    fn temp_path(name: &str) -> PathBuf { env::temp_dir().join(format!("ota-{}-{}", process::id(), name)) }
+
+    #[test]
+    fn seal_and_open_token() {
+        let key_file = temp_path("seal-key");
+        fs::write(&key_file, b"some secret key").expect("write key file");
+        let store = TokenStore {
+            path:     temp_path("seal-token"),
+            key_file: Some(key_file.clone()),
+        };
+        let token = AccessToken {
+            access_token: "some-token".into(),
+            token_type:   "bearer".into(),
+            expires_in:   3600,
+            scope:        "namespace.default".into(),
+            issued_at:    Some(1),
+        };
+
+        assert!(store.open().expect("missing store").is_none());
+        store.seal(&token).expect("seal");
+        assert!(!fs::read(&store.path).expect("read store").windows(10).any(|w| w == b"some-token"));
+        let opened = store.open().expect("open").expect("token");
+        assert_eq!(opened.access_token, "some-token");
+        assert_eq!(opened.scope, "namespace.default");
+
+        fs::write(&key_file, b"another key").expect("write key file");
+        assert!(store.open().is_err());
+        let _ = fs::remove_file(&key_file);
+        let _ = fs::remove_file(&store.path);
+    }
+}
@@ -0,0 +1,242 @@
+use serde_json::{self, Value};
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use error::Result;
+use http::millis;
+use transport::{redact_headers, scrub_form, scrub_json, Body, Request, Response, Transport};
+
+
+/// The end of the HAR file, written again after each appended entry.
+const TRAILER: &[u8] = b"\n]}}\n";
+
+/// Write every request and response in HAR format, with credentials redacted.
+///
+/// Each entry is appended in place of the closing brackets, which are then
+/// written again, so the trace is complete even when the command exits with
+/// an error.
+pub struct TraceTransport {
+    inner: Arc<dyn Transport>,
+    path:  PathBuf,
+    file:  Mutex<Option<File>>,
+}
+
+impl TraceTransport {
+    pub fn new(inner: Arc<dyn Transport>, path: impl Into<PathBuf>) -> Self {
+        TraceTransport {
+            inner,
+            path: path.into(),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Append the entry, creating the file on the first one.
+    ///
+    /// When an append fails partway, the closing brackets are written again
+    /// after the previous entry so the file stays valid without it.
+    fn append(&self, entry: &Value) -> Result<()> {
+        let mut entry = serde_json::to_vec(entry)?;
+        entry.extend_from_slice(TRAILER);
+
+        let mut file = self.file.lock().expect("trace lock");
+        match *file {
+            Some(ref mut file) => {
+                let end = file.seek(SeekFrom::End(-(TRAILER.len() as i64)))?;
+                let mut out = b",\n".to_vec();
+                out.append(&mut entry);
+                if let Err(err) = file.write_all(&out) {
+                    let _ = file.set_len(end).and_then(|_| file.seek(SeekFrom::Start(end))).and_then(|_| file.write_all(TRAILER));
+                    Err(err)?
+                }
+            }
+            None => {
+                debug!("writing trace to {:?}", self.path);
+                let creator = json!({"name": "ota", "version": env!("CARGO_PKG_VERSION")});
+                let mut out = format!("{{\"log\": {{\"version\": \"1.2\", \"creator\": {}, \"entries\": [\n", creator).into_bytes();
+                out.append(&mut entry);
+                let mut created = File::create(&self.path)?;
+                created.write_all(&out)?;
+                *file = Some(created);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for TraceTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        let result = self.inner.execute(req);
+        let entry = har_entry(req, result.as_ref().ok(), started, millis(timer.elapsed()));
+        if let Err(err) = self.append(&entry) {
+            warn!("unable to write trace to {:?}: {}", self.path, err);
+        }
+        result
+    }
+}
+
+
+/// Describe the exchange as a HAR entry. A failed request has status 0.
+fn har_entry(req: &Request, resp: Option<&Response>, started: SystemTime, time: u64) -> Value {
+    let pairs = |pairs: Vec<(String, String)>| -> Vec<Value> {
+        pairs.into_iter().map(|(name, value)| json!({"name": name, "value": value})).collect()
+    };
+
+    let mut request = json!({
+        "method": req.method.as_str(),
+        "url": req.url.as_str(),
+        "httpVersion": "HTTP/1.1",
+        "cookies": [],
+        "headers": pairs(redact_headers(&req.headers)),
+        "queryString": pairs(req.query_pairs()),
+        "headersSize": -1,
+        "bodySize": -1,
+    });
+    let post_data = match req.body {
+        Some(Body::Json(ref json)) => Some(json!({"mimeType": "application/json", "text": scrub_json(json.clone()).to_string()})),
+        Some(Body::Form(ref form)) => Some(json!({"mimeType": "application/x-www-form-urlencoded", "text": scrub_form(form)})),
+        Some(Body::Multipart(ref parts)) => Some(json!({
+            "mimeType": "multipart/form-data",
+            "params": parts
+                .iter()
+                .map(|part| json!({"name": part.name, "fileName": part.path.display().to_string()}))
+                .collect::<Vec<_>>(),
+        })),
+        None => None,
+    };
+    if let Some(post_data) = post_data {
+        request["postData"] = post_data;
+    }
+
+    let response = match resp {
+        Some(resp) => {
+            let text = match serde_json::from_slice::<Value>(&resp.body) {
+                Ok(json) => scrub_json(json).to_string(),
+                Err(_) => resp.text(),
+            };
+            json!({
+                "status": resp.status().as_u16(),
+                "statusText": resp.status().canonical_reason().unwrap_or_default(),
+                "httpVersion": "HTTP/1.1",
+                "cookies": [],
+                "headers": pairs(redact_headers(&resp.headers)),
+                "content": {
+                    "size": resp.body.len(),
+                    "mimeType": resp.header("content-type").unwrap_or_default(),
+                    "text": text,
+                },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": resp.body.len(),
+            })
+        }
+        None => json!({
+            "status": 0,
+            "statusText": "",
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": [],
+            "content": {"size": 0, "mimeType": ""},
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": -1,
+        }),
+    };
+
+    json!({
+        "startedDateTime": iso8601(started),
+        "time": time,
+        "request": request,
+        "response": response,
+        "cache": {},
+        "timings": {"send": 0, "wait": time, "receive": 0},
+    })
+}
+
+/// Format the time as an ISO 8601 UTC timestamp with milliseconds.
+fn iso8601(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since.subsec_millis()
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use std::{env, fs, process, time::Duration};
+
+    use transport::FakeTransport;
+
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(UNIX_EPOCH + Duration::from_millis(951_782_400_123)), "2000-02-29T00:00:00.123Z");
+        assert_eq!(iso8601(UNIX_EPOCH + Duration::from_secs(1_792_190_384)), "2026-10-16T22:39:44.000Z");
+    }
+
+    #[test]
+    fn write_redacted_har() {
+        let path = env::temp_dir().join(format!("ota-{}-trace.har", process::id()));
+        let fake = FakeTransport::new();
+        let _ = fake.respond(Response::new(StatusCode::OK, r#"{"access_token": "secret-token"}"#));
+        let trace = TraceTransport::new(Arc::new(fake), &path);
+
+        let req = Request::post("http://auth/token?scope=x")
+            .expect("request")
+            .basic_auth("id", "client-secret")
+            .form(&[("grant_type", "client_credentials")])
+            .expect("form");
+        let _ = trace.execute(&req).expect("token");
+        assert!(trace.execute(&req).is_err());
+
+        let har: Value = serde_json::from_slice(&fs::read(&path).expect("har")).expect("json");
+        let entries = har["log"]["entries"].as_array().expect("entries");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["request"]["headers"][0]["value"], "<redacted>");
+        assert_eq!(entries[0]["request"]["queryString"][0], json!({"name": "scope", "value": "x"}));
+        assert_eq!(entries[0]["request"]["postData"]["text"], "grant_type=client_credentials");
+        assert_eq!(entries[0]["response"]["content"]["text"], r#"{"access_token":"<redacted>"}"#);
+        assert_eq!(entries[1]["response"]["status"], 0);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn keep_response_when_trace_fails() {
+        let fake = FakeTransport::new();
+        let _ = fake.respond(Response::new(StatusCode::OK, "ok"));
+        let trace = TraceTransport::new(Arc::new(fake), env::temp_dir().join("ota-missing-dir").join("trace.har"));
+        let resp = trace.execute(&Request::get("http://registry/").expect("request")).expect("response");
+        assert_eq!(resp.text(), "ok");
+    }
+}
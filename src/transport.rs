@@ -0,0 +1,450 @@
+use openssl::base64;
+use reqwest::{header::CONTENT_TYPE, multipart::Form, Client, Method, StatusCode, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{self, Value};
+use serde_urlencoded;
+use std::{
+    collections::VecDeque,
+    io::Read,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use error::{Error, Result};
+
+
+/// Send a `Request` and return the complete `Response`.
+pub trait Transport: Send + Sync {
+    fn execute(&self, req: &Request) -> Result<Response>;
+}
+
+
+/// An HTTP request that can be inspected and sent more than once.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method:  Method,
+    pub url:     Url,
+    pub headers: Vec<(String, String)>,
+    pub body:    Option<Body>,
+}
+
+/// The body of a `Request`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Body {
+    Json(Value),
+    Form(String),
+    Multipart(Vec<Part>),
+}
+
+/// A file uploaded as part of a multipart body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Part {
+    pub fn file(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Part {
+            name: name.into(),
+            path: path.into(),
+        }
+    }
+}
+
+impl Request {
+    pub fn new(method: Method, url: impl AsRef<str>) -> Result<Self> {
+        Ok(Request {
+            method,
+            url: Url::parse(url.as_ref())?,
+            headers: Vec::new(),
+            body: None,
+        })
+    }
+
+    pub fn get(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::GET, url) }
+    pub fn post(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::POST, url) }
+    pub fn put(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::PUT, url) }
+    pub fn delete(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::DELETE, url) }
+
+    /// Append the serialized pairs to the URL query.
+    pub fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Result<Self> {
+        let encoded = serde_urlencoded::to_string(query).map_err(|err| Error::Parse(format!("query: {}", err)))?;
+        if !encoded.is_empty() {
+            let query = match self.url.query() {
+                Some(existing) if !existing.is_empty() => format!("{}&{}", existing, encoded),
+                _ => encoded,
+            };
+            self.url.set_query(Some(&query));
+        }
+        Ok(self)
+    }
+
+    /// Set a header, replacing any existing value.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_lowercase(), value.into()));
+        self
+    }
+
+    /// Return the first value of a header.
+    pub fn header_value(&self, name: &str) -> Option<&str> { find_header(&self.headers, name) }
+
+    pub fn basic_auth(self, user: &str, password: &str) -> Self {
+        let credentials = base64::encode_block(format!("{}:{}", user, password).as_bytes());
+        self.header("authorization", format!("Basic {}", credentials))
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Self { self.header("authorization", format!("Bearer {}", token)) }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Result<Self> {
+        self.body = Some(Body::Json(serde_json::to_value(body)?));
+        Ok(self)
+    }
+
+    pub fn form<T: Serialize + ?Sized>(mut self, form: &T) -> Result<Self> {
+        let encoded = serde_urlencoded::to_string(form).map_err(|err| Error::Parse(format!("form: {}", err)))?;
+        self.body = Some(Body::Form(encoded));
+        Ok(self)
+    }
+
+    pub fn multipart(mut self, parts: Vec<Part>) -> Self {
+        self.body = Some(Body::Multipart(parts));
+        self
+    }
+
+    /// Return the URL query as decoded pairs.
+    pub fn query_pairs(&self) -> Vec<(String, String)> { self.url.query_pairs().into_owned().collect() }
+}
+
+
+/// A complete HTTP response.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status:  StatusCode,
+    pub headers: Vec<(String, String)>,
+    pub body:    Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode { self.status }
+
+    /// Return the first value of a header.
+    pub fn header(&self, name: &str) -> Option<&str> { find_header(&self.headers, name) }
+
+    /// Parse the JSON body, or return `Error::DryRun` for a dry-run response.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        if let Some(req) = self.header(DRY_RUN) {
+            Err(Error::DryRun(req.into()))?
+        }
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    pub fn text(&self) -> String { String::from_utf8_lossy(&self.body).into_owned() }
+}
+
+pub(crate) fn find_header<'h>(headers: &'h [(String, String)], name: &str) -> Option<&'h str> {
+    headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, val)| val.as_str())
+}
+
+
+/// The replacement for any secret value that is logged or saved.
+pub const REDACTED: &str = "<redacted>";
+
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie"];
+const SECRET_FIELDS: &[&str] = &["access_token", "refresh_token", "client_secret", "password", "token", "secret"];
+
+/// Copy the headers with any credentials redacted.
+pub fn redact_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if SECRET_HEADERS.iter().any(|secret| name.eq_ignore_ascii_case(secret)) {
+                (name.clone(), REDACTED.into())
+            } else {
+                (name.clone(), value.clone())
+            }
+        })
+        .collect()
+}
+
+/// Redact the value of any secret field in a JSON document.
+pub fn scrub_json(mut json: Value) -> Value {
+    match json {
+        Value::Object(ref mut map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) {
+                    *value = Value::String(REDACTED.into());
+                } else {
+                    *value = scrub_json(value.take());
+                }
+            }
+        }
+        Value::Array(ref mut values) => {
+            for value in values.iter_mut() {
+                *value = scrub_json(value.take());
+            }
+        }
+        _ => (),
+    }
+    json
+}
+
+/// Redact the value of any secret field in a url-encoded form.
+pub fn scrub_form(form: &str) -> String {
+    form.split('&')
+        .map(|pair| match pair.find('=') {
+            Some(pos) if SECRET_FIELDS.contains(&&pair[..pos]) => format!("{}={}", &pair[..pos], REDACTED),
+            _ => pair.into(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+
+/// Send requests over the network with a shared `reqwest::Client`.
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> Self { ReqwestTransport { client } }
+}
+
+impl Transport for ReqwestTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        let mut builder = self.client.request(req.method.clone(), req.url.clone());
+        for (name, value) in &req.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder = match req.body {
+            Some(Body::Json(ref json)) => builder.json(json),
+            Some(Body::Form(ref form)) => builder.header(CONTENT_TYPE, "application/x-www-form-urlencoded").body(form.clone()),
+            Some(Body::Multipart(ref parts)) => {
+                let mut form = Form::new();
+                for part in parts {
+                    form = form.file(part.name.clone(), &part.path)?;
+                }
+                builder.multipart(form)
+            }
+            None => builder,
+        };
+
+        let mut resp = builder.send()?;
+        let mut body = Vec::new();
+        let _ = resp.read_to_end(&mut body)?;
+        Ok(Response {
+            status: resp.status(),
+            headers: resp
+                .headers()
+                .iter()
+                .map(|(name, val)| (name.as_str().into(), val.to_str().unwrap_or_default().into()))
+                .collect(),
+            body,
+        })
+    }
+}
+
+
+/// An in-memory transport that returns canned responses and records each request.
+#[derive(Clone, Default)]
+pub struct FakeTransport {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    responses: VecDeque<Response>,
+    requests:  Vec<Request>,
+}
+
+impl FakeTransport {
+    pub fn new() -> Self { Self::default() }
+
+    /// Queue a response to return to the next unanswered request.
+    pub fn respond(&self, resp: Response) -> &Self {
+        self.state.lock().expect("fake transport lock").responses.push_back(resp);
+        self
+    }
+
+    /// Return every request received so far.
+    pub fn requests(&self) -> Vec<Request> { self.state.lock().expect("fake transport lock").requests.clone() }
+}
+
+impl Transport for FakeTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        let mut state = self.state.lock().expect("fake transport lock");
+        state.requests.push(req.clone());
+        state
+            .responses
+            .pop_front()
+            .ok_or_else(|| Error::NotFound(format!("Canned response for {} {}", req.method, req.url), None))
+    }
+}
+
+
+/// Options for printing requests instead of sending them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DryRun {
+    /// Print each request as a `curl` command.
+    pub curl: bool,
+    /// Send read-only GET requests as normal.
+    pub allow_gets: bool,
+}
+
+/// The header that marks a synthetic dry-run response with the method and
+/// URL of the request that was not sent.
+const DRY_RUN: &str = "x-ota-dry-run";
+
+/// Print each request and return an empty `204 No Content` response.
+pub struct DryRunTransport {
+    inner:   Arc<dyn Transport>,
+    options: DryRun,
+}
+
+impl DryRunTransport {
+    pub fn new(inner: Arc<dyn Transport>, options: DryRun) -> Self { DryRunTransport { inner, options } }
+}
+
+impl Transport for DryRunTransport {
+    fn execute(&self, req: &Request) -> Result<Response> {
+        if self.options.allow_gets && req.method == Method::GET {
+            debug!("dry run: sending {} {}", req.method, req.url);
+            return self.inner.execute(req);
+        }
+        if self.options.curl {
+            println!("{}", req.to_curl());
+        } else {
+            println!("{}", req.describe());
+        }
+        let mut resp = Response::new(StatusCode::NO_CONTENT, Vec::new());
+        resp.headers.push((DRY_RUN.into(), format!("{} {}", req.method, req.url)));
+        Ok(resp)
+    }
+}
+
+impl Request {
+    /// Describe the request with any credentials redacted.
+    pub fn describe(&self) -> String {
+        let mut url = self.url.clone();
+        url.set_query(None);
+        let mut out = format!("{} {}\n", self.method, url);
+        let pairs = self.query_pairs();
+        if !pairs.is_empty() {
+            out.push_str("query:\n");
+            for (key, val) in pairs {
+                out.push_str(&format!("  {}={}\n", key, val));
+            }
+        }
+        if !self.headers.is_empty() {
+            out.push_str("headers:\n");
+            for (name, val) in redact_headers(&self.headers) {
+                out.push_str(&format!("  {}: {}\n", name, val));
+            }
+        }
+        match self.body {
+            Some(Body::Json(ref json)) => {
+                let json = serde_json::to_string_pretty(&scrub_json(json.clone())).unwrap_or_default();
+                out.push_str(&format!("body:\n  {}\n", json.replace('\n', "\n  ")));
+            }
+            Some(Body::Form(ref form)) => out.push_str(&format!("body:\n  {}\n", scrub_form(form))),
+            Some(Body::Multipart(ref parts)) => {
+                out.push_str("multipart:\n");
+                for part in parts {
+                    out.push_str(&format!("  {}: @{}\n", part.name, part.path.display()));
+                }
+            }
+            None => (),
+        }
+        out
+    }
+
+    /// Format the request as a `curl` command.
+    ///
+    /// The authorization header reads the token from `$OTA_TOKEN` so the
+    /// command can be run without revealing it.
+    pub fn to_curl(&self) -> String {
+        let mut args = vec!["curl".to_string(), "-X".into(), self.method.to_string(), shell_quote(self.url.as_str())];
+        for (name, val) in &self.headers {
+            let header = match name.as_str() {
+                "authorization" => format!("\"{}: Bearer $OTA_TOKEN\"", name),
+                _ => shell_quote(&format!("{}: {}", name, val)),
+            };
+            args.extend(vec!["-H".into(), header]);
+        }
+        match self.body {
+            Some(Body::Json(ref json)) => {
+                args.extend(vec!["-H".into(), shell_quote("content-type: application/json")]);
+                args.extend(vec!["--data".into(), shell_quote(&scrub_json(json.clone()).to_string())]);
+            }
+            Some(Body::Form(ref form)) => args.extend(vec!["--data".into(), shell_quote(&scrub_form(form))]),
+            Some(Body::Multipart(ref parts)) => {
+                for part in parts {
+                    args.extend(vec!["-F".into(), shell_quote(&format!("{}=@{}", part.name, part.path.display()))]);
+                }
+            }
+            None => (),
+        }
+        args.join(" ")
+    }
+}
+
+/// Quote a string as a single shell word.
+fn shell_quote(word: &str) -> String { format!("'{}'", word.replace('\'', r#"'\''"#)) }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn campaign_request() -> Request {
+        Request::post("https://campaigner/api/v2/campaigns")
+            .and_then(|req| req.query(&[("limit", "1")]))
+            .and_then(|req| req.bearer_auth("secret-token").json(&json!({"name": "it's live", "password": "hunter2"})))
+            .expect("request")
+    }
+
+    #[test]
+    fn describe_redacted_request() {
+        let out = campaign_request().describe();
+        assert!(out.starts_with("POST https://campaigner/api/v2/campaigns\nquery:\n  limit=1\n"), "{}", out);
+        assert!(out.contains("authorization: <redacted>"), "{}", out);
+        assert!(out.contains(r#""name": "it's live""#), "{}", out);
+        assert!(!out.contains("secret-token") && !out.contains("hunter2"), "{}", out);
+
+        let curl = campaign_request().to_curl();
+        assert!(curl.starts_with("curl -X POST 'https://campaigner/api/v2/campaigns?limit=1'"), "{}", curl);
+        assert!(curl.contains(r#"-H "authorization: Bearer $OTA_TOKEN""#), "{}", curl);
+        assert!(curl.contains(r#"it'\''s live"#), "{}", curl);
+        assert!(!curl.contains("secret-token") && !curl.contains("hunter2"), "{}", curl);
+    }
+
+    #[test]
+    fn dry_run_sends_only_gets() {
+        let fake = FakeTransport::new();
+        let _ = fake.respond(Response::new(StatusCode::OK, "[]"));
+        let options = DryRun { curl: false, allow_gets: true };
+        let dry_run = DryRunTransport::new(Arc::new(fake.clone()), options);
+
+        let resp = dry_run.execute(&campaign_request()).expect("dry run");
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        match resp.json::<Value>() {
+            Err(Error::DryRun(req)) => assert_eq!(req, "POST https://campaigner/api/v2/campaigns?limit=1"),
+            other => panic!("expected a dry run error, got {:?}", other),
+        }
+        assert!(fake.requests().is_empty());
+
+        let resp = dry_run.execute(&Request::get("https://registry/api/v1/devices").expect("request")).expect("get");
+        assert_eq!(resp.text(), "[]");
+        assert_eq!(fake.requests().len(), 1);
+    }
+}